@@ -8,7 +8,7 @@ const AXIS_RANGE: f32 = 128.0;
 fn main() {
     App::new()
         .add_plugins(DefaultPlugins)
-        .add_plugin(JoyconsPlugin)
+        .add_plugin(JoyconsPlugin::default())
         .add_startup_system(setup)
         .add_system(spawn_squares_for_gamepads)
         .add_system(update_squares)
@@ -43,13 +43,7 @@ fn spawn_squares_for_gamepads(
             let x = rng.gen_range((-center.x + margin)..(center.x - margin));
             let y = rng.gen_range((-center.y + margin)..(center.y - margin));
 
-            let color = joycons
-                .get_info(event.gamepad)
-                .map(|info| {
-                    let color = info.color.body;
-                    Color::hex(&color.to_string()[1..]).unwrap()
-                })
-                .unwrap_or(Color::WHITE);
+            let color = joycons.gamepad_color(event.gamepad);
 
             commands
                 .spawn(SpatialBundle::from_transform(Transform::from_xyz(
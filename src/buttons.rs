@@ -0,0 +1,378 @@
+use std::time::Instant;
+
+use bevy_input::gamepad::GamepadButtonType;
+use joycon::joycon_sys::input::{
+    ButtonsStatus, LeftButtons, MiddleButtons, RightButtons, WhichController,
+};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::stick::StickSide;
+
+/// A physical button on a joycon or pro controller.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum JoyconButton {
+    Up,
+    Down,
+    Left,
+    Right,
+    L,
+    ZL,
+    A,
+    B,
+    X,
+    Y,
+    R,
+    ZR,
+    /// The SL button on the rail of a single joycon.
+    SL,
+    /// The SR button on the rail of a single joycon.
+    SR,
+    Minus,
+    Plus,
+    Home,
+    Capture,
+    /// Pressing the left stick in.
+    LeftStick,
+    /// Pressing the right stick in.
+    RightStick,
+}
+
+impl JoyconButton {
+    /// Every button, in declaration order.
+    pub const ALL: [JoyconButton; 20] = [
+        JoyconButton::Up,
+        JoyconButton::Down,
+        JoyconButton::Left,
+        JoyconButton::Right,
+        JoyconButton::L,
+        JoyconButton::ZL,
+        JoyconButton::A,
+        JoyconButton::B,
+        JoyconButton::X,
+        JoyconButton::Y,
+        JoyconButton::R,
+        JoyconButton::ZR,
+        JoyconButton::SL,
+        JoyconButton::SR,
+        JoyconButton::Minus,
+        JoyconButton::Plus,
+        JoyconButton::Home,
+        JoyconButton::Capture,
+        JoyconButton::LeftStick,
+        JoyconButton::RightStick,
+    ];
+
+    pub fn is_pressed(self, buttons: &ButtonsStatus) -> bool {
+        let left = buttons.left;
+        let middle = buttons.middle;
+        let right = buttons.right;
+
+        match self {
+            JoyconButton::Up => left.contains(LeftButtons::UP),
+            JoyconButton::Down => left.contains(LeftButtons::DOWN),
+            JoyconButton::Left => left.contains(LeftButtons::LEFT),
+            JoyconButton::Right => left.contains(LeftButtons::RIGHT),
+            JoyconButton::L => left.contains(LeftButtons::L),
+            JoyconButton::ZL => left.contains(LeftButtons::ZL),
+            JoyconButton::A => right.contains(RightButtons::A),
+            JoyconButton::B => right.contains(RightButtons::B),
+            JoyconButton::X => right.contains(RightButtons::X),
+            JoyconButton::Y => right.contains(RightButtons::Y),
+            JoyconButton::R => right.contains(RightButtons::R),
+            JoyconButton::ZR => right.contains(RightButtons::ZR),
+            JoyconButton::SL => left.contains(LeftButtons::SL) || right.contains(RightButtons::SL),
+            JoyconButton::SR => left.contains(LeftButtons::SR) || right.contains(RightButtons::SR),
+            JoyconButton::Minus => middle.contains(MiddleButtons::MINUS),
+            JoyconButton::Plus => middle.contains(MiddleButtons::PLUS),
+            JoyconButton::Home => middle.contains(MiddleButtons::HOME),
+            JoyconButton::Capture => middle.contains(MiddleButtons::CAPTURE),
+            JoyconButton::LeftStick => middle.contains(MiddleButtons::LSTICK),
+            JoyconButton::RightStick => middle.contains(MiddleButtons::RSTICK),
+        }
+    }
+
+    /// Whether the button is one of the four D-pad directions.
+    pub fn is_direction(self) -> bool {
+        matches!(
+            self,
+            JoyconButton::Up | JoyconButton::Down | JoyconButton::Left | JoyconButton::Right
+        )
+    }
+}
+
+/// How a controller's D-pad is reported.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DpadMode {
+    /// Each direction is reported as a button, as set by the controller's
+    /// button mapping.
+    #[default]
+    Buttons,
+    /// The directions are reported as the `DPadX` and `DPadY` axes, like a
+    /// hat switch, and aren't reported as buttons. Each axis is -1.0, 0.0 or
+    /// 1.0, with up and right being positive, as seen in the controller's
+    /// layout. Right joycons have no D-pad, so this doesn't affect them.
+    Hat,
+}
+
+/// How a single joycon is held, which decides how its buttons and stick are
+/// mapped. Pro controllers always use the same mapping.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum JoyconLayout {
+    /// Held horizontally with both hands, with the stick on the left.
+    #[default]
+    Sideways,
+    /// Held vertically in one hand like a remote, with the stick on top.
+    /// Only the buttons reachable with the thumb and index finger are mapped.
+    UprightSolo,
+}
+
+/// A complete table of which gamepad button each of a controller's buttons is
+/// reported as, to replace the built-in mapping with
+/// `Joycons::set_button_map`. Buttons that aren't in the table aren't
+/// reported, and several buttons can be reported as the same gamepad button.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ButtonMap {
+    pub buttons: Vec<(JoyconButton, GamepadButtonType)>,
+}
+
+impl ButtonMap {
+    /// The built-in mapping for a controller held in the given layout, to
+    /// start a custom mapping from.
+    pub fn default_for(which: WhichController, layout: JoyconLayout) -> Self {
+        Self {
+            buttons: button_mapping(which, layout).to_vec(),
+        }
+    }
+}
+
+/// Which gamepad button each of a controller's buttons is reported as. Held
+/// sideways, a single joycon's buttons under the thumb become the face buttons
+/// and SL/SR become the shoulder buttons. Stick presses follow the stick, so a
+/// single joycon's stick press is `LeftThumb`, until
+/// [`assigned_stick_press`] moves it along with the stick. Face buttons are
+/// mapped by position, so a pro controller's B button is `South`.
+pub(crate) fn button_mapping(
+    which: WhichController,
+    layout: JoyconLayout,
+) -> &'static [(JoyconButton, GamepadButtonType)] {
+    match (which, layout) {
+        (WhichController::LeftJoyCon, JoyconLayout::UprightSolo) => &[
+            (JoyconButton::Down, GamepadButtonType::South),
+            (JoyconButton::Right, GamepadButtonType::East),
+            (JoyconButton::Left, GamepadButtonType::West),
+            (JoyconButton::Up, GamepadButtonType::North),
+            (JoyconButton::L, GamepadButtonType::LeftTrigger),
+            (JoyconButton::ZL, GamepadButtonType::LeftTrigger2),
+            (JoyconButton::Minus, GamepadButtonType::Start),
+            (JoyconButton::Capture, GamepadButtonType::Select),
+            (JoyconButton::LeftStick, GamepadButtonType::LeftThumb),
+        ],
+        (WhichController::RightJoyCon, JoyconLayout::UprightSolo) => &[
+            (JoyconButton::B, GamepadButtonType::South),
+            (JoyconButton::A, GamepadButtonType::East),
+            (JoyconButton::Y, GamepadButtonType::West),
+            (JoyconButton::X, GamepadButtonType::North),
+            (JoyconButton::R, GamepadButtonType::RightTrigger),
+            (JoyconButton::ZR, GamepadButtonType::RightTrigger2),
+            (JoyconButton::Plus, GamepadButtonType::Start),
+            (JoyconButton::Home, GamepadButtonType::Mode),
+            (JoyconButton::RightStick, GamepadButtonType::LeftThumb),
+        ],
+        (WhichController::LeftJoyCon, JoyconLayout::Sideways) => &[
+            (JoyconButton::Left, GamepadButtonType::South),
+            (JoyconButton::Down, GamepadButtonType::East),
+            (JoyconButton::Up, GamepadButtonType::West),
+            (JoyconButton::Right, GamepadButtonType::North),
+            (JoyconButton::SL, GamepadButtonType::LeftTrigger),
+            (JoyconButton::SR, GamepadButtonType::RightTrigger),
+            (JoyconButton::L, GamepadButtonType::LeftTrigger2),
+            (JoyconButton::ZL, GamepadButtonType::RightTrigger2),
+            (JoyconButton::Minus, GamepadButtonType::Start),
+            (JoyconButton::Capture, GamepadButtonType::Select),
+            (JoyconButton::LeftStick, GamepadButtonType::LeftThumb),
+        ],
+        (WhichController::RightJoyCon, JoyconLayout::Sideways) => &[
+            (JoyconButton::A, GamepadButtonType::South),
+            (JoyconButton::X, GamepadButtonType::East),
+            (JoyconButton::B, GamepadButtonType::West),
+            (JoyconButton::Y, GamepadButtonType::North),
+            (JoyconButton::SL, GamepadButtonType::LeftTrigger),
+            (JoyconButton::SR, GamepadButtonType::RightTrigger),
+            (JoyconButton::R, GamepadButtonType::LeftTrigger2),
+            (JoyconButton::ZR, GamepadButtonType::RightTrigger2),
+            (JoyconButton::Plus, GamepadButtonType::Start),
+            (JoyconButton::Home, GamepadButtonType::Mode),
+            (JoyconButton::RightStick, GamepadButtonType::LeftThumb),
+        ],
+        // The pro controller's triggers are digital, so they're only ever
+        // reported as fully pressed or released, unlike on a standard gamepad
+        // with analog triggers. Capture has no standard equivalent, so it's
+        // left unmapped.
+        (WhichController::ProController, _) => &[
+            (JoyconButton::B, GamepadButtonType::South),
+            (JoyconButton::A, GamepadButtonType::East),
+            (JoyconButton::Y, GamepadButtonType::West),
+            (JoyconButton::X, GamepadButtonType::North),
+            (JoyconButton::L, GamepadButtonType::LeftTrigger),
+            (JoyconButton::R, GamepadButtonType::RightTrigger),
+            (JoyconButton::ZL, GamepadButtonType::LeftTrigger2),
+            (JoyconButton::ZR, GamepadButtonType::RightTrigger2),
+            (JoyconButton::Up, GamepadButtonType::DPadUp),
+            (JoyconButton::Down, GamepadButtonType::DPadDown),
+            (JoyconButton::Left, GamepadButtonType::DPadLeft),
+            (JoyconButton::Right, GamepadButtonType::DPadRight),
+            (JoyconButton::Minus, GamepadButtonType::Select),
+            (JoyconButton::Plus, GamepadButtonType::Start),
+            (JoyconButton::Home, GamepadButtonType::Mode),
+            (JoyconButton::LeftStick, GamepadButtonType::LeftThumb),
+            (JoyconButton::RightStick, GamepadButtonType::RightThumb),
+        ],
+    }
+}
+
+/// Buttons that are reported as one extra gamepad button while they're all
+/// held, set with `Joycons::map_chord`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct Chord {
+    pub(crate) buttons: Vec<JoyconButton>,
+    pub(crate) button: GamepadButtonType,
+    pub(crate) suppress: bool,
+}
+
+impl Chord {
+    pub(crate) fn is_held(&self, buttons: &ButtonsStatus) -> bool {
+        !self.buttons.is_empty() && self.buttons.iter().all(|button| button.is_pressed(buttons))
+    }
+}
+
+/// Returns the D-pad's hat axes as an `(x, y)` vector, as seen when the
+/// controller is held in the given layout, or `None` if it has no D-pad.
+pub(crate) fn dpad_hat(
+    which: WhichController,
+    layout: JoyconLayout,
+    buttons: &ButtonsStatus,
+) -> Option<[f32; 2]> {
+    let axis = |negative: JoyconButton, positive: JoyconButton| {
+        positive.is_pressed(buttons) as i8 as f32 - negative.is_pressed(buttons) as i8 as f32
+    };
+
+    match (which, layout) {
+        // Held sideways, the D-pad is rotated a quarter turn clockwise.
+        (WhichController::LeftJoyCon, JoyconLayout::Sideways) => Some([
+            axis(JoyconButton::Up, JoyconButton::Down),
+            axis(JoyconButton::Left, JoyconButton::Right),
+        ]),
+        (WhichController::LeftJoyCon, JoyconLayout::UprightSolo)
+        | (WhichController::ProController, _) => Some([
+            axis(JoyconButton::Left, JoyconButton::Right),
+            axis(JoyconButton::Down, JoyconButton::Up),
+        ]),
+        (WhichController::RightJoyCon, _) => None,
+    }
+}
+
+/// Moves a single joycon's stick press, if it's mapped to a thumb button, to
+/// the thumb button of the stick it's assigned to with
+/// `Joycons::set_stick_assignment`.
+pub(crate) fn assigned_stick_press(
+    which: WhichController,
+    stick_assignment: StickSide,
+    (joycon_button, button_type): (JoyconButton, GamepadButtonType),
+) -> (JoyconButton, GamepadButtonType) {
+    let is_stick_press = matches!(
+        joycon_button,
+        JoyconButton::LeftStick | JoyconButton::RightStick
+    );
+    let is_thumb = matches!(
+        button_type,
+        GamepadButtonType::LeftThumb | GamepadButtonType::RightThumb
+    );
+    if which == WhichController::ProController || !is_stick_press || !is_thumb {
+        return (joycon_button, button_type);
+    }
+
+    (joycon_button, stick_assignment.thumb_button())
+}
+
+/// Auto-fire state of a button.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Turbo {
+    hz: f32,
+    held_since: Option<Instant>,
+}
+
+impl Turbo {
+    pub(crate) fn new(hz: f32) -> Self {
+        Self {
+            hz,
+            held_since: None,
+        }
+    }
+
+    /// Returns whether the button should be reported as pressed. While it's
+    /// held, it alternates between pressed and released `hz` times a second,
+    /// starting with pressed.
+    pub(crate) fn apply(&mut self, pressed: bool, now: Instant) -> bool {
+        if !pressed {
+            self.held_since = None;
+            return false;
+        }
+
+        let held_since = *self.held_since.get_or_insert(now);
+        let half_periods = (now - held_since).as_secs_f32() * self.hz * 2.0;
+        half_periods as u64 % 2 == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stick_press(
+        which: WhichController,
+        layout: JoyconLayout,
+    ) -> (JoyconButton, GamepadButtonType) {
+        button_mapping(which, layout)
+            .iter()
+            .copied()
+            .find(|(button, _)| {
+                matches!(button, JoyconButton::LeftStick | JoyconButton::RightStick)
+            })
+            .unwrap()
+    }
+
+    #[test]
+    fn stick_press_follows_a_reassigned_stick() {
+        for which in [WhichController::LeftJoyCon, WhichController::RightJoyCon] {
+            let press = stick_press(which, JoyconLayout::Sideways);
+            assert_eq!(
+                assigned_stick_press(which, StickSide::Left, press).1,
+                GamepadButtonType::LeftThumb
+            );
+            assert_eq!(
+                assigned_stick_press(which, StickSide::Right, press).1,
+                GamepadButtonType::RightThumb
+            );
+        }
+    }
+
+    #[test]
+    fn pro_controller_stick_presses_stay_on_their_stick() {
+        let press = (JoyconButton::LeftStick, GamepadButtonType::LeftThumb);
+        assert_eq!(
+            assigned_stick_press(WhichController::ProController, StickSide::Right, press),
+            press
+        );
+    }
+
+    #[test]
+    fn stick_press_mapped_elsewhere_isnt_moved() {
+        let press = (JoyconButton::LeftStick, GamepadButtonType::South);
+        assert_eq!(
+            assigned_stick_press(WhichController::LeftJoyCon, StickSide::Right, press),
+            press
+        );
+    }
+}
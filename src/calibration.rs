@@ -0,0 +1,402 @@
+use std::time::{Duration, Instant};
+
+use bevy_utils::tracing::warn;
+use joycon::{
+    joycon_sys::{
+        input::{Stick, WhichController},
+        spi::{SticksCalibration, UserSticksCalibration},
+    },
+    JoyCon as JoyconDevice,
+};
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::stick::StickSide;
+
+/// Raw stick values are 12 bits, so uncalibrated sticks rest around the middle
+/// of that range.
+const DEFAULT_STICK_CENTER: f64 = 2048.0;
+/// How far from the center a typical stick reaches, in raw units.
+const DEFAULT_STICK_RANGE: f64 = 1400.0;
+const MAX_RAW_STICK_VALUE: u16 = 0xfff;
+
+/// How long the stick needs to stay still to find its center.
+const CENTER_DURATION: Duration = Duration::from_secs(1);
+/// How far apart, in raw units, the values of a centered stick may be. More
+/// than this means the player is touching it.
+const CENTER_TOLERANCE: f64 = 100.0;
+/// How far from the center, in raw units, the stick needs to reach in each
+/// direction while rotating. Real sticks reach roughly twice as far.
+const MIN_CALIBRATION_REACH: f64 = 600.0;
+/// How close to the center, in raw units, a released stick needs to be.
+const RELEASE_TOLERANCE: f64 = 200.0;
+
+/// Where the calibration used for a controller's sticks comes from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CalibrationSource {
+    /// The calibration written to the controller at the factory.
+    Factory,
+    /// A calibration the user made, e.g. from the Switch's settings.
+    User,
+    /// The controller's calibration couldn't be read or doesn't make sense, so
+    /// generic defaults are used. Sticks may be noticeably off-center.
+    Default,
+}
+
+/// The steps of a guided stick calibration, started with
+/// `Joycons::start_calibration`, for a calibration screen to show the
+/// matching prompt.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CalibrationState {
+    /// The player should leave the stick alone. This lasts until the stick
+    /// stayed still for a second, and starts over if it moves.
+    Center,
+    /// The player should rotate the stick around its edge, until it reached
+    /// far enough in every direction.
+    Rotate,
+    /// The player should let go of the stick. It can still be rotated some
+    /// more, until it's back at the center.
+    Release,
+    /// The new calibration is in use.
+    Done,
+}
+
+/// A stick calibration made with `Joycons::start_calibration`, replacing the
+/// controller's own, in raw units. Kept in [`PersistedControllerSettings`] to
+/// be restored in later sessions.
+///
+/// [`PersistedControllerSettings`]: crate::PersistedControllerSettings
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct CustomCalibration {
+    center: [f64; 2],
+    min: [f64; 2],
+    max: [f64; 2],
+}
+
+impl CustomCalibration {
+    pub(crate) fn new(center: [f64; 2], min: [f64; 2], max: [f64; 2]) -> Self {
+        Self { center, min, max }
+    }
+
+    /// Whether each axis has its center strictly between its minimum and
+    /// maximum, all within the range of a raw stick value. Others, e.g. from
+    /// corrupted settings, would divide by zero in [`Self::apply`].
+    pub(crate) fn is_valid(&self) -> bool {
+        (0..2).all(|axis| {
+            0.0 <= self.min[axis]
+                && self.min[axis] < self.center[axis]
+                && self.center[axis] < self.max[axis]
+                && self.max[axis] <= f64::from(MAX_RAW_STICK_VALUE)
+        })
+    }
+
+    pub(crate) fn apply(&self, stick: Stick) -> [f64; 2] {
+        let raw = [stick.x(), stick.y()].map(f64::from);
+        [0, 1].map(|axis| {
+            let center = self.center[axis];
+            let value = if raw[axis] < center {
+                (raw[axis] - center) / (center - self.min[axis])
+            } else {
+                (raw[axis] - center) / (self.max[axis] - center)
+            };
+            value.clamp(-1.0, 1.0)
+        })
+    }
+}
+
+/// Walks through the steps of [`CalibrationState`] as raw values of the stick
+/// come in.
+pub(crate) struct CalibrationAssistant {
+    pub(crate) side: StickSide,
+    pub(crate) state: CalibrationState,
+    /// When the stick was first seen still during [`CalibrationState::Center`].
+    still_since: Option<Instant>,
+    /// Sum and number of values while centering.
+    sum: [f64; 2],
+    count: u32,
+    /// The extent of the values in the current step.
+    min: [f64; 2],
+    max: [f64; 2],
+    center: [f64; 2],
+}
+
+impl CalibrationAssistant {
+    pub(crate) fn new(side: StickSide) -> Self {
+        Self {
+            side,
+            state: CalibrationState::Center,
+            still_since: None,
+            sum: [0.0; 2],
+            count: 0,
+            min: [f64::MAX; 2],
+            max: [f64::MIN; 2],
+            center: [0.0; 2],
+        }
+    }
+
+    /// Handles a raw value of the stick, returning the new state if it
+    /// changed.
+    pub(crate) fn update(&mut self, (x, y): (u16, u16), now: Instant) -> Option<CalibrationState> {
+        let raw = [f64::from(x), f64::from(y)];
+        for axis in 0..2 {
+            self.min[axis] = self.min[axis].min(raw[axis]);
+            self.max[axis] = self.max[axis].max(raw[axis]);
+        }
+
+        let next = match self.state {
+            CalibrationState::Center => {
+                let moved = (0..2).any(|axis| self.max[axis] - self.min[axis] > CENTER_TOLERANCE);
+                if moved {
+                    self.restart_centering(raw, now);
+                    return None;
+                }
+
+                self.sum = [self.sum[0] + raw[0], self.sum[1] + raw[1]];
+                self.count += 1;
+                let still_since = *self.still_since.get_or_insert(now);
+                if now.duration_since(still_since) < CENTER_DURATION {
+                    return None;
+                }
+
+                self.center = self.sum.map(|sum| sum / f64::from(self.count));
+                self.min = self.center;
+                self.max = self.center;
+                CalibrationState::Rotate
+            }
+            CalibrationState::Rotate => {
+                let reached = (0..2).all(|axis| {
+                    self.center[axis] - self.min[axis] >= MIN_CALIBRATION_REACH
+                        && self.max[axis] - self.center[axis] >= MIN_CALIBRATION_REACH
+                });
+                if !reached {
+                    return None;
+                }
+                CalibrationState::Release
+            }
+            CalibrationState::Release => {
+                let released =
+                    (0..2).all(|axis| (raw[axis] - self.center[axis]).abs() <= RELEASE_TOLERANCE);
+                if !released {
+                    return None;
+                }
+                CalibrationState::Done
+            }
+            CalibrationState::Done => return None,
+        };
+
+        self.state = next;
+        Some(next)
+    }
+
+    fn restart_centering(&mut self, raw: [f64; 2], now: Instant) {
+        self.still_since = Some(now);
+        self.sum = raw;
+        self.count = 1;
+        self.min = raw;
+        self.max = raw;
+    }
+
+    /// Returns the calibration, once it's done and if it's usable.
+    pub(crate) fn calibration(&self) -> Option<CustomCalibration> {
+        let calibration = CustomCalibration::new(self.center, self.min, self.max);
+        (self.state == CalibrationState::Done && calibration.is_valid()).then_some(calibration)
+    }
+}
+
+/// Figures out which calibration is used for the controller's sticks.
+pub(crate) fn calibration_source(
+    joycon_device: &mut JoyconDevice,
+    which: WhichController,
+) -> CalibrationSource {
+    // The user calibration is used if there's one, otherwise the factory one.
+    // Each is checked on its own, so that a broken one doesn't hide the other.
+    match joycon_device.read_spi::<UserSticksCalibration>() {
+        Ok(user) if is_user_calibration_valid(&user, which) => return CalibrationSource::User,
+        Ok(_) => {}
+        Err(e) => warn!("Error reading user stick calibration: {}", e),
+    }
+
+    match joycon_device.read_spi::<SticksCalibration>() {
+        Ok(calibration) if is_calibration_valid(&calibration, which) => CalibrationSource::Factory,
+        Ok(_) => CalibrationSource::Default,
+        Err(e) => {
+            warn!("Error reading stick calibration: {}", e);
+            CalibrationSource::Default
+        }
+    }
+}
+
+/// Checks that the controller has a user calibration, and that it's
+/// plausible, as with [`is_calibration_valid`]. A pro controller may have one
+/// for only one of its sticks, but the ones it has all need to be plausible.
+fn is_user_calibration_valid(user: &UserSticksCalibration, which: WhichController) -> bool {
+    let left = user
+        .left
+        .calib()
+        .map(|stick| is_stick_valid(stick.min(), stick.center(), stick.max()));
+    let right = user
+        .right
+        .calib()
+        .map(|stick| is_stick_valid(stick.min(), stick.center(), stick.max()));
+
+    match which {
+        WhichController::LeftJoyCon => left == Some(true),
+        WhichController::RightJoyCon => right == Some(true),
+        WhichController::ProController => {
+            (left.is_some() || right.is_some()) && left != Some(false) && right != Some(false)
+        }
+    }
+}
+
+/// Checks that the calibration of the controller's sticks is plausible, i.e.
+/// that each axis has its center between its minimum and maximum, all within
+/// the range of a raw stick value.
+fn is_calibration_valid(calibration: &SticksCalibration, which: WhichController) -> bool {
+    let left = || {
+        is_stick_valid(
+            calibration.left.min(),
+            calibration.left.center(),
+            calibration.left.max(),
+        )
+    };
+    let right = || {
+        is_stick_valid(
+            calibration.right.min(),
+            calibration.right.center(),
+            calibration.right.max(),
+        )
+    };
+
+    match which {
+        WhichController::LeftJoyCon => left(),
+        WhichController::RightJoyCon => right(),
+        WhichController::ProController => left() && right(),
+    }
+}
+
+fn is_stick_valid(min: (u16, u16), center: (u16, u16), max: (u16, u16)) -> bool {
+    let is_axis_valid = |min: u16, center: u16, max: u16| {
+        min < center && center < max && max <= MAX_RAW_STICK_VALUE
+    };
+
+    is_axis_valid(min.0, center.0, max.0) && is_axis_valid(min.1, center.1, max.1)
+}
+
+/// Converts a raw stick value using generic defaults instead of the
+/// controller's own calibration.
+pub(crate) fn uncalibrated_stick_value(stick: Stick) -> [f64; 2] {
+    [stick.x(), stick.y()]
+        .map(|raw| ((f64::from(raw) - DEFAULT_STICK_CENTER) / DEFAULT_STICK_RANGE).clamp(-1.0, 1.0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CENTER: u16 = 2048;
+
+    /// Leaves the stick at the center until the assistant moves on to
+    /// rotating, returning when that happened.
+    fn centered(assistant: &mut CalibrationAssistant, start: Instant) -> Instant {
+        assert_eq!(assistant.update((CENTER, CENTER), start), None);
+        let now = start + CENTER_DURATION;
+        assert_eq!(
+            assistant.update((CENTER, CENTER), now),
+            Some(CalibrationState::Rotate)
+        );
+        now
+    }
+
+    #[test]
+    fn centering_restarts_when_the_stick_moves() {
+        let mut assistant = CalibrationAssistant::new(StickSide::Left);
+        let start = Instant::now();
+        let moved_at = start + Duration::from_millis(700);
+
+        assert_eq!(assistant.update((CENTER, CENTER), start), None);
+        assert_eq!(assistant.update((CENTER + 500, CENTER), moved_at), None);
+        // A second after the stick first stood still, but not after it moved.
+        assert_eq!(
+            assistant.update((CENTER + 500, CENTER), start + CENTER_DURATION),
+            None
+        );
+        assert_eq!(assistant.state, CalibrationState::Center);
+        assert_eq!(
+            assistant.update((CENTER + 500, CENTER), moved_at + CENTER_DURATION),
+            Some(CalibrationState::Rotate)
+        );
+        assert_eq!(
+            assistant.center,
+            [f64::from(CENTER + 500), f64::from(CENTER)]
+        );
+    }
+
+    #[test]
+    fn rotating_needs_full_reach_on_both_axes() {
+        let mut assistant = CalibrationAssistant::new(StickSide::Left);
+        let now = centered(&mut assistant, Instant::now());
+
+        assert_eq!(assistant.update((CENTER + 700, CENTER), now), None);
+        assert_eq!(assistant.update((CENTER - 700, CENTER), now), None);
+        assert_eq!(assistant.update((CENTER, CENTER + 700), now), None);
+        // Not far enough down yet.
+        assert_eq!(assistant.update((CENTER, CENTER - 500), now), None);
+        assert_eq!(assistant.state, CalibrationState::Rotate);
+        assert_eq!(
+            assistant.update((CENTER, CENTER - 700), now),
+            Some(CalibrationState::Release)
+        );
+    }
+
+    #[test]
+    fn releasing_the_stick_finishes() {
+        let mut assistant = CalibrationAssistant::new(StickSide::Left);
+        let now = centered(&mut assistant, Instant::now());
+        for stick in [
+            (CENTER + 700, CENTER),
+            (CENTER - 700, CENTER),
+            (CENTER, CENTER + 700),
+        ] {
+            assistant.update(stick, now);
+        }
+        assert_eq!(
+            assistant.update((CENTER, CENTER - 700), now),
+            Some(CalibrationState::Release)
+        );
+        assert_eq!(assistant.calibration(), None);
+
+        assert_eq!(assistant.update((CENTER + 300, CENTER), now), None);
+        assert_eq!(
+            assistant.update((CENTER + 50, CENTER - 50), now),
+            Some(CalibrationState::Done)
+        );
+        assert_eq!(
+            assistant.calibration(),
+            Some(CustomCalibration {
+                center: [f64::from(CENTER); 2],
+                min: [f64::from(CENTER - 700); 2],
+                max: [f64::from(CENTER + 700); 2],
+            })
+        );
+    }
+
+    #[test]
+    fn calibrations_without_a_span_are_invalid() {
+        let center = f64::from(CENTER);
+        let flat = CustomCalibration {
+            center: [center; 2],
+            min: [center; 2],
+            max: [center; 2],
+        };
+        assert!(!flat.is_valid());
+
+        let usable = CustomCalibration {
+            center: [center; 2],
+            min: [center - 700.0; 2],
+            max: [center + 700.0; 2],
+        };
+        assert!(usable.is_valid());
+    }
+}
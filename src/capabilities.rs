@@ -0,0 +1,27 @@
+use joycon::joycon_sys::input::WhichController;
+
+/// What a controller supports, so that callers can check before using a
+/// feature that would fail on it, or hide options that aren't available.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct JoyconCapabilities {
+    /// The controller's firmware version, as `(major, minor)`. Some
+    /// subcommands are only supported by newer firmware.
+    pub firmware_version: (u8, u8),
+    /// Whether the controller has an IR camera. Only right joycons do.
+    pub has_ir: bool,
+    /// Whether the controller has an NFC reader.
+    pub has_nfc: bool,
+}
+
+impl JoyconCapabilities {
+    pub(crate) fn new(which: WhichController, [major, minor]: [u8; 2]) -> Self {
+        Self {
+            firmware_version: (major, minor),
+            has_ir: which == WhichController::RightJoyCon,
+            has_nfc: matches!(
+                which,
+                WhichController::RightJoyCon | WhichController::ProController
+            ),
+        }
+    }
+}
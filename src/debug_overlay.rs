@@ -0,0 +1,147 @@
+use std::{fmt::Write, time::Instant};
+
+use bevy_app::{App, Plugin};
+use bevy_asset::AssetServer;
+use bevy_ecs::{
+    component::Component,
+    query::With,
+    system::{Commands, Local, Query, Res, Resource},
+};
+use bevy_input::gamepad::Gamepad;
+use bevy_render::color::Color;
+use bevy_text::{Text, TextStyle};
+use bevy_ui::{node_bundles::TextBundle, PositionType, Style, UiRect, Val};
+use bevy_utils::{default, HashMap};
+
+use crate::{JoyconButton, Joycons};
+
+/// Shows the state of every connected joycon in a text overlay: its sticks,
+/// pressed buttons, battery and update rate. It's meant for checking that
+/// controllers work, and only uses `Joycons`' public API.
+///
+/// The overlay needs a camera that renders UI, and a font, since Bevy doesn't
+/// have a default one.
+pub struct JoyconDebugPlugin {
+    /// Path of the overlay's font, as loaded by the `AssetServer`.
+    pub font_path: String,
+}
+
+impl Plugin for JoyconDebugPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(OverlayFont(self.font_path.clone()))
+            .add_startup_system(spawn_overlay)
+            .add_system(update_overlay);
+    }
+}
+
+#[derive(Resource)]
+struct OverlayFont(String);
+
+#[derive(Component)]
+struct DebugOverlay;
+
+/// Counts how many frames each second had a new snapshot. Reports that arrive
+/// between two frames only count once, so this is at most the frame rate, and
+/// not the joycon's report rate.
+#[derive(Default)]
+struct UpdateRate {
+    sequence: Option<u8>,
+    updates: u32,
+    since: Option<Instant>,
+    per_second: u32,
+}
+
+impl UpdateRate {
+    fn update(&mut self, sequence: Option<u8>, now: Instant) {
+        if sequence != self.sequence {
+            self.sequence = sequence;
+            self.updates += 1;
+        }
+
+        let since = *self.since.get_or_insert(now);
+        if now.duration_since(since).as_secs() >= 1 {
+            self.per_second = self.updates;
+            self.updates = 0;
+            self.since = Some(now);
+        }
+    }
+}
+
+fn spawn_overlay(mut commands: Commands, asset_server: Res<AssetServer>, font: Res<OverlayFont>) {
+    let style = TextStyle {
+        font: asset_server.load(font.0.as_str()),
+        font_size: 16.0,
+        color: Color::WHITE,
+    };
+
+    commands.spawn((
+        TextBundle::from_section("", style).with_style(Style {
+            position_type: PositionType::Absolute,
+            position: UiRect {
+                top: Val::Px(8.0),
+                left: Val::Px(8.0),
+                ..default()
+            },
+            ..default()
+        }),
+        DebugOverlay,
+    ));
+}
+
+fn update_overlay(
+    joycons: Res<Joycons>,
+    mut rates: Local<HashMap<Gamepad, UpdateRate>>,
+    mut overlays: Query<&mut Text, With<DebugOverlay>>,
+) {
+    let now = Instant::now();
+    let mut connected = joycons.iter().collect::<Vec<_>>();
+    connected.sort_by_key(|(gamepad, _)| gamepad.id);
+    rates.retain(|gamepad, _| connected.iter().any(|(other, _)| other == gamepad));
+
+    // Writing to a String can't fail.
+    let mut text = String::new();
+    if connected.is_empty() {
+        text.push_str("No joycons connected\n");
+    }
+
+    for (gamepad, info) in connected {
+        let _ = writeln!(
+            text,
+            "{:?}: {} ({})",
+            gamepad, info.product_string, info.serial_number
+        );
+
+        let Some(snapshot) = joycons.snapshot(gamepad) else {
+            let _ = writeln!(text, "  waiting for the first report");
+            continue;
+        };
+
+        let rate = rates.entry(gamepad).or_default();
+        rate.update(snapshot.sequence, now);
+
+        let pressed = JoyconButton::ALL
+            .iter()
+            .filter(|button| button.is_pressed(&snapshot.buttons))
+            .map(|button| format!("{:?}", button))
+            .collect::<Vec<_>>();
+
+        let _ = writeln!(
+            text,
+            "  left stick: ({:.2}, {:.2})  right stick: ({:.2}, {:.2})",
+            snapshot.left_stick.x,
+            snapshot.left_stick.y,
+            snapshot.right_stick.x,
+            snapshot.right_stick.y,
+        );
+        let _ = writeln!(text, "  pressed: {}", pressed.join(" "));
+        let _ = writeln!(
+            text,
+            "  battery: {:?}  updates/s: {}",
+            snapshot.battery, rate.per_second
+        );
+    }
+
+    for mut overlay in &mut overlays {
+        overlay.sections[0].value = text.clone();
+    }
+}
@@ -0,0 +1,262 @@
+#[cfg(feature = "raw-subcommands")]
+use std::sync::mpsc::Sender;
+use std::{
+    sync::mpsc::Receiver,
+    time::{Duration, Instant},
+};
+
+use anyhow::{Context, Result};
+use bevy_utils::tracing::{error, warn};
+use joycon::{
+    joycon_sys::{
+        light::{PlayerLight, PlayerLights},
+        output::{RumbleSide, SubcommandRequest},
+    },
+    JoyCon as JoyconDevice,
+};
+
+use crate::rumble::RumbleEnvelope;
+
+/// How long the player lights blink when identifying a joycon.
+const IDENTIFY_DURATION: Duration = Duration::from_millis(1000);
+/// How long the joycon rumbles when identifying it.
+const IDENTIFY_RUMBLE_DURATION: Duration = Duration::from_millis(200);
+
+/// The "set HCI state" subcommand, and the state that disconnects the joycon
+/// and puts it to sleep until a button is pressed.
+const SET_HCI_STATE_SUBCOMMAND: u8 = 0x06;
+const HCI_STATE_DISCONNECT: u8 = 0x00;
+
+/// Requests from the main thread to a joycon's polling thread.
+pub(crate) enum DeviceCommand {
+    /// Blink the player lights and rumble briefly, so that the player can tell
+    /// which controller this is.
+    Identify,
+    /// Turn the accelerometer and gyro on or off.
+    SetImuEnabled(bool),
+    /// Show which player the joycon belongs to with its player lights.
+    SetPlayerNumber(u8),
+    /// Play a rumble envelope, replacing any that's already playing.
+    PlayRumbleEnvelope(RumbleEnvelope),
+    /// Disconnect from bluetooth. The joycon goes to sleep, and reconnects
+    /// when one of its buttons is pressed.
+    Disconnect,
+    /// Send an arbitrary subcommand and send back the raw reply.
+    #[cfg(feature = "raw-subcommands")]
+    RawSubcommand {
+        id: u8,
+        data: Vec<u8>,
+        reply: Sender<Result<Vec<u8>>>,
+    },
+}
+
+/// Runs commands sent to a joycon. Owned by the joycon's polling thread, which
+/// is the only one allowed to talk to the device.
+pub(crate) struct DeviceControl {
+    commands: Receiver<DeviceCommand>,
+    rumble_enabled: bool,
+    /// The player lights that should be shown when nothing else is going on.
+    lights: PlayerLights,
+    identify_until: Option<Instant>,
+    rumble_until: Option<Instant>,
+    /// The envelope being played, and when it started.
+    envelope: Option<(RumbleEnvelope, Instant)>,
+}
+
+impl DeviceControl {
+    pub(crate) fn new(commands: Receiver<DeviceCommand>, rumble_enabled: bool) -> Self {
+        Self {
+            commands,
+            rumble_enabled,
+            lights: PlayerLights::new(
+                PlayerLight::Off,
+                PlayerLight::Off,
+                PlayerLight::Off,
+                PlayerLight::Off,
+            ),
+            identify_until: None,
+            rumble_until: None,
+            envelope: None,
+        }
+    }
+
+    /// Runs pending commands and ends effects that are over. Errors are only
+    /// logged, because they shouldn't stop the joycon from being polled.
+    pub(crate) fn update(&mut self, device: &mut JoyconDevice) {
+        while let Ok(command) = self.commands.try_recv() {
+            if let Err(e) = self.run(device, command) {
+                error!("Error running joycon command: {}", e);
+            }
+        }
+
+        let now = Instant::now();
+
+        if let Some((envelope, started)) = self.envelope {
+            let rumble = match envelope.amplitude_at(now - started) {
+                Some(amplitude) => Some(RumbleSide::from_freq(
+                    envelope.frequency,
+                    amplitude,
+                    envelope.frequency,
+                    amplitude,
+                )),
+                None => {
+                    self.envelope = None;
+                    None
+                }
+            };
+            if let Err(e) = set_rumble(device, rumble) {
+                error!("Error playing rumble envelope: {}", e);
+            }
+        }
+
+        if self.rumble_until.map_or(false, |until| now >= until) {
+            self.rumble_until = None;
+            if let Err(e) = set_rumble(device, None) {
+                error!("Error stopping joycon rumble: {}", e);
+            }
+        }
+
+        if self.identify_until.map_or(false, |until| now >= until) {
+            self.identify_until = None;
+            if let Err(e) = device
+                .set_player_light(self.lights)
+                .context("Restoring player lights")
+            {
+                error!("Error ending joycon identification: {}", e);
+            }
+        }
+    }
+
+    fn run(&mut self, device: &mut JoyconDevice, command: DeviceCommand) -> Result<()> {
+        let now = Instant::now();
+
+        match command {
+            DeviceCommand::Identify => {
+                device
+                    .set_player_light(PlayerLights::new(
+                        PlayerLight::Blinking,
+                        PlayerLight::Blinking,
+                        PlayerLight::Blinking,
+                        PlayerLight::Blinking,
+                    ))
+                    .context("Blinking player lights")?;
+                self.identify_until = Some(now + IDENTIFY_DURATION);
+
+                if self.rumble_enabled {
+                    set_rumble(device, Some(RumbleSide::from_freq(320., 0.5, 160., 0.5)))?;
+                    self.rumble_until = Some(now + IDENTIFY_RUMBLE_DURATION);
+                } else {
+                    warn!("Rumble isn't enabled, identifying joycon with lights only");
+                }
+            }
+
+            DeviceCommand::SetImuEnabled(enabled) => {
+                if enabled {
+                    device.enable_imu().context("Enabling IMU")?;
+                } else {
+                    device.disable_imu().context("Disabling IMU")?;
+                }
+            }
+
+            DeviceCommand::SetPlayerNumber(player_number) => {
+                self.lights = player_lights(player_number);
+                // Otherwise, the lights are restored when identification ends.
+                if self.identify_until.is_none() {
+                    device
+                        .set_player_light(self.lights)
+                        .context("Setting player lights")?;
+                }
+            }
+
+            DeviceCommand::PlayRumbleEnvelope(envelope) => {
+                if self.rumble_enabled {
+                    self.envelope = Some((envelope, now));
+                    self.rumble_until = None;
+                } else {
+                    warn!("Rumble isn't enabled, not playing rumble envelope");
+                }
+            }
+
+            DeviceCommand::Disconnect => {
+                // The joycon may drop the connection before replying.
+                if let Err(e) = disconnect(device) {
+                    warn!("No reply to disconnecting the joycon: {}", e);
+                }
+            }
+
+            #[cfg(feature = "raw-subcommands")]
+            DeviceCommand::RawSubcommand { id, data, reply } => {
+                // The caller might have dropped the receiver, which is fine.
+                let _ = reply.send(raw_subcommand(device, id, &data));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Sets the joycon's HCI state to disconnected, which drops its bluetooth
+/// connection.
+fn disconnect(device: &mut JoyconDevice) -> Result<()> {
+    let request = SubcommandRequest::from_raw(SET_HCI_STATE_SUBCOMMAND, &[HCI_STATE_DISCONNECT])
+        .context("Building HCI state subcommand")?;
+    device
+        .call_subcmd_wait(request)
+        .context("Setting HCI state")?;
+    Ok(())
+}
+
+#[cfg(feature = "raw-subcommands")]
+fn raw_subcommand(device: &mut JoyconDevice, id: u8, data: &[u8]) -> Result<Vec<u8>> {
+    let request = SubcommandRequest::from_raw(id, data)
+        .with_context(|| format!("Building subcommand {:#04x}", id))?;
+    let reply = device
+        .call_subcmd_wait(request)
+        .with_context(|| format!("Sending subcommand {:#04x}", id))?;
+    Ok(reply.as_bytes().to_vec())
+}
+
+/// Returns which of the 4 player lights are lit for a player number, from
+/// left to right, or `None` if it isn't between 1 and 8. Players 1 to 4 get
+/// the light at their position, and players 5 to 8 get the same patterns the
+/// Switch uses for them.
+pub fn player_light_pattern(player_number: u8) -> Option<[bool; 4]> {
+    Some(match player_number {
+        1 => [true, false, false, false],
+        2 => [false, true, false, false],
+        3 => [false, false, true, false],
+        4 => [false, false, false, true],
+        5 => [true, false, false, true],
+        6 => [true, false, true, false],
+        7 => [true, false, true, true],
+        8 => [false, true, true, false],
+        _ => return None,
+    })
+}
+
+fn player_lights(player_number: u8) -> PlayerLights {
+    let [first, second, third, fourth] = player_light_pattern(player_number)
+        .unwrap_or_default()
+        .map(|lit| {
+            if lit {
+                PlayerLight::On
+            } else {
+                PlayerLight::Off
+            }
+        });
+    PlayerLights::new(first, second, third, fourth)
+}
+
+pub(crate) fn enable_rumble(device: &mut JoyconDevice) -> Result<()> {
+    device.set_rumble(true).context("Enabling rumble")
+}
+
+/// Rumbles both sides of the controller, or stops rumbling if `rumble` is
+/// `None`.
+fn set_rumble(device: &mut JoyconDevice, rumble: Option<RumbleSide>) -> Result<()> {
+    // Zero amplitude is silent whatever the frequencies are.
+    let rumble = rumble.unwrap_or_else(|| RumbleSide::from_freq(320., 0., 160., 0.));
+    device
+        .rumble((Some(rumble), Some(rumble)))
+        .context("Sending rumble")
+}
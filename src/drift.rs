@@ -0,0 +1,97 @@
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+
+/// Sticks further from the center than this are being pushed, so they don't
+/// tell how much the stick drifts.
+const REST_THRESHOLD: f64 = 0.25;
+/// How much a stick may move between two reports and still count as resting.
+/// More than this means the player is touching it.
+const REST_STABILITY: f64 = 0.02;
+/// How often a sample is added to the history.
+const DRIFT_SAMPLE_INTERVAL: Duration = Duration::from_secs(60);
+/// How many samples are kept. Older ones are dropped, except for the first
+/// one, which is kept to compare against.
+const DRIFT_HISTORY_LEN: usize = 60;
+
+/// How far a stick rests from its center while nobody touches it.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DriftSample {
+    /// When the sample was taken. It averages the resting values since the
+    /// previous sample.
+    pub time: Instant,
+    /// Distance from the center, from 0.0 to 1.0.
+    pub magnitude: f32,
+}
+
+/// The drift of one of a controller's sticks, sampled about once a minute
+/// since the controller connected, e.g. to warn a player that their stick's
+/// drift got worse. Drift is measured before `Joycons::zero_stick`, so zeroing
+/// a stick doesn't hide it.
+#[derive(Clone, Debug, Default)]
+pub struct DriftHistory {
+    first: Option<DriftSample>,
+    samples: VecDeque<DriftSample>,
+    /// Resting values since the last sample.
+    sum: f64,
+    count: u32,
+    period_start: Option<Instant>,
+    previous: Option<[f64; 2]>,
+}
+
+impl DriftHistory {
+    /// The samples, oldest first. Only the last hour is kept.
+    pub fn samples(&self) -> impl Iterator<Item = &DriftSample> {
+        self.samples.iter()
+    }
+
+    /// The first sample, even once it's out of [`Self::samples`].
+    pub fn session_start(&self) -> Option<DriftSample> {
+        self.first
+    }
+
+    pub fn current(&self) -> Option<DriftSample> {
+        self.samples.back().copied()
+    }
+
+    /// How much the drift grew since the first sample. Negative if it shrank.
+    pub fn change(&self) -> Option<f32> {
+        Some(self.current()?.magnitude - self.first?.magnitude)
+    }
+
+    /// Takes the stick's value from a report, before any processing.
+    pub(crate) fn update(&mut self, [x, y]: [f64; 2], now: Instant) {
+        let previous = self.previous.replace([x, y]);
+        let period_start = *self.period_start.get_or_insert(now);
+
+        let magnitude = x.hypot(y);
+        let stable = previous.map_or(false, |[previous_x, previous_y]| {
+            (x - previous_x).hypot(y - previous_y) <= REST_STABILITY
+        });
+        if magnitude < REST_THRESHOLD && stable {
+            self.sum += magnitude;
+            self.count += 1;
+        }
+
+        if now.duration_since(period_start) < DRIFT_SAMPLE_INTERVAL {
+            return;
+        }
+        self.period_start = Some(now);
+        if self.count == 0 {
+            return;
+        }
+
+        let sample = DriftSample {
+            time: now,
+            magnitude: (self.sum / f64::from(self.count)) as f32,
+        };
+        self.sum = 0.0;
+        self.count = 0;
+        self.first.get_or_insert(sample);
+        if self.samples.len() == DRIFT_HISTORY_LEN {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(sample);
+    }
+}
@@ -0,0 +1,76 @@
+use std::time::Instant;
+
+use bevy_input::gamepad::{Gamepad, GamepadAxisType};
+
+use crate::{calibration::CalibrationState, stick::StickSide, IdleDisconnectAction};
+
+/// Sent when a joycon connects with stick calibration data that doesn't make
+/// sense. Its sticks use generic defaults instead, which are usable but may be
+/// a bit off-center, so this is a good time to offer the player to calibrate
+/// the controller.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct JoyconCalibrationWarning {
+    pub gamepad: Gamepad,
+}
+
+/// Sent along with the gamepad event when one of a joycon's axes changes, if
+/// `JoyconsPlugin::axis_change_events` is enabled. Unlike the gamepad event,
+/// it has the previous value too, e.g. for recording or analyzing input.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct JoyconAxisChanged {
+    pub gamepad: Gamepad,
+    pub axis: GamepadAxisType,
+    /// The previous value, or 0.0 if there wasn't one.
+    pub old: f32,
+    pub new: f32,
+}
+
+/// Sent when a joycon is detected while `JoyconsPlugin::max_controllers` are
+/// already connected. The joycon isn't opened, but it will be once a slot
+/// frees up. Sent once each time the joycon is detected.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct JoyconRejected {
+    pub serial_number: String,
+}
+
+/// Sent when a guided calibration started with `Joycons::start_calibration`
+/// moves to its next step.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct JoyconCalibrationStateChanged {
+    pub gamepad: Gamepad,
+    /// The physical stick being calibrated.
+    pub side: StickSide,
+    pub state: CalibrationState,
+}
+
+/// Sent when a joycon takes one of the slots reserved with
+/// `Joycons::reserve_slots`, as soon as it's opened.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct JoyconSlotAssigned {
+    pub gamepad: Gamepad,
+    /// Index of the slot, starting from 0. Its player number is `slot + 1`.
+    pub slot: usize,
+}
+
+/// Sent when a joycon had no input for `JoyconSettings::idle_timeout`, e.g. to
+/// switch to an attract mode.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct JoyconIdle {
+    pub gamepad: Gamepad,
+    /// When the joycon's last input was.
+    pub since: Instant,
+}
+
+/// Sent when a joycon had no input for `JoyconsPlugin::idle_disconnect`, once
+/// it was released or told to disconnect.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct JoyconIdleDisconnected {
+    pub gamepad: Gamepad,
+    pub action: IdleDisconnectAction,
+}
+
+/// Sent on the first input from a joycon after a [`JoyconIdle`] event.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct JoyconWake {
+    pub gamepad: Gamepad,
+}
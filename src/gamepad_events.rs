@@ -0,0 +1,117 @@
+use bevy_ecs::event::EventWriter;
+use bevy_input::gamepad::{
+    Gamepad, GamepadAxisType, GamepadButtonType, GamepadEventRaw, GamepadEventType, GamepadInfo,
+};
+use bevy_utils::tracing::info;
+
+use crate::events::JoyconAxisChanged;
+
+/// The event that joycon input is sent as. This module is the only one that
+/// knows about Bevy's gamepad event API, so that adapting to a new version of
+/// it only has to happen here.
+pub(crate) type RawGamepadEvent = GamepadEventRaw;
+
+/// Sends gamepad events while keeping count of them.
+pub(crate) struct GamepadEvents<'a, 'w, 's> {
+    writer: &'a mut EventWriter<'w, 's, RawGamepadEvent>,
+    sent: usize,
+    log_buttons: bool,
+    axis_changes: Option<&'a mut EventWriter<'w, 's, JoyconAxisChanged>>,
+    muted: bool,
+}
+
+impl<'a, 'w, 's> GamepadEvents<'a, 'w, 's> {
+    pub(crate) fn new(
+        writer: &'a mut EventWriter<'w, 's, RawGamepadEvent>,
+        log_buttons: bool,
+    ) -> Self {
+        Self {
+            writer,
+            sent: 0,
+            log_buttons,
+            axis_changes: None,
+            muted: false,
+        }
+    }
+
+    /// Also sends a `JoyconAxisChanged` for every axis that changes.
+    pub(crate) fn with_axis_changes(
+        mut self,
+        axis_changes: &'a mut EventWriter<'w, 's, JoyconAxisChanged>,
+    ) -> Self {
+        self.axis_changes = Some(axis_changes);
+        self
+    }
+
+    /// Drops events instead of sending them, e.g. for a released joycon
+    /// whose reports are still processed.
+    pub(crate) fn set_muted(&mut self, muted: bool) {
+        self.muted = muted;
+    }
+
+    pub(crate) fn is_muted(&self) -> bool {
+        self.muted
+    }
+
+    /// Number of events sent so far.
+    pub(crate) fn sent(&self) -> usize {
+        self.sent
+    }
+
+    pub(crate) fn connected(&mut self, gamepad: Gamepad, name: String) {
+        self.send(gamepad, GamepadEventType::Connected(GamepadInfo { name }));
+    }
+
+    pub(crate) fn disconnected(&mut self, gamepad: Gamepad) {
+        self.send(gamepad, GamepadEventType::Disconnected);
+    }
+
+    /// `old` is the last value sent for the axis, if any.
+    pub(crate) fn axis_changed(
+        &mut self,
+        gamepad: Gamepad,
+        axis: GamepadAxisType,
+        old: Option<f32>,
+        value: f32,
+    ) {
+        if self.muted {
+            return;
+        }
+
+        self.send(gamepad, GamepadEventType::AxisChanged(axis, value));
+
+        if let Some(axis_changes) = &mut self.axis_changes {
+            if old != Some(value) {
+                axis_changes.send(JoyconAxisChanged {
+                    gamepad,
+                    axis,
+                    old: old.unwrap_or(0.0),
+                    new: value,
+                });
+            }
+        }
+    }
+
+    pub(crate) fn button_changed(
+        &mut self,
+        gamepad: Gamepad,
+        button_type: GamepadButtonType,
+        value: f32,
+    ) {
+        if self.muted {
+            return;
+        }
+
+        if self.log_buttons {
+            let state = if value > 0.0 { "pressed" } else { "released" };
+            info!("{:?} {:?} {}", gamepad, button_type, state);
+        }
+
+        self.send(gamepad, GamepadEventType::ButtonChanged(button_type, value));
+    }
+
+    fn send(&mut self, gamepad: Gamepad, event_type: GamepadEventType) {
+        self.writer.send(GamepadEventRaw::new(gamepad, event_type));
+        self.sent += 1;
+    }
+}
@@ -0,0 +1,88 @@
+use std::{ops::Range, sync::Arc, time::Instant};
+
+use bevy_ecs::system::Resource;
+use bevy_input::gamepad::Gamepad;
+use bevy_math::Vec2;
+use joycon::hidapi::DeviceInfo;
+
+use crate::JoyconReport;
+
+/// Called on the main thread for every new report, before it's turned into
+/// gamepad events. Changes made to the report affect the events and the other
+/// accessors.
+pub type ReportHook = Arc<dyn Fn(Gamepad, &mut JoyconReport) + Send + Sync>;
+
+/// Called during detection for every joycon that's about to be opened, to
+/// decide whether to open it and how. Set with
+/// `JoyconsPlugin::connection_policy`. Without one, every joycon is opened
+/// with a new gamepad.
+///
+/// A rejected joycon isn't asked about again until it disconnects and comes
+/// back.
+pub type ConnectionPolicy = Arc<dyn Fn(&ConnectionRequest) -> ConnectionDecision + Send + Sync>;
+
+/// A joycon that's about to be opened, passed to a [`ConnectionPolicy`].
+pub struct ConnectionRequest<'a> {
+    pub device_info: &'a DeviceInfo,
+    /// The serial number, normalized like
+    /// [`JoyconInfo::serial_number`](crate::JoyconInfo::serial_number).
+    pub serial_number: &'a str,
+    pub product_string: &'a str,
+}
+
+/// What a [`ConnectionPolicy`] decided about a joycon.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ConnectionDecision {
+    Accept(ConnectionOptions),
+    Reject,
+}
+
+/// The ids that [`ConnectionOptions::gamepad`] may use. They're above the ids
+/// of native gamepads, and below the ids of new joycon gamepads.
+pub const REQUESTED_GAMEPAD_IDS: Range<usize> = 0x4000_0000..0x8000_0000;
+
+/// How to open a joycon that a [`ConnectionPolicy`] accepted.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ConnectionOptions {
+    /// The gamepad to use instead of a new one, e.g. to keep the same gamepad
+    /// for a joycon across sessions. Its id has to be in
+    /// [`REQUESTED_GAMEPAD_IDS`]. Ignored, with a warning, if it isn't, or if
+    /// it's already in use. Joycons that come back during
+    /// `JoyconsPlugin::hid_loss_grace` always keep their gamepad.
+    pub gamepad: Option<Gamepad>,
+    /// The name used in the connection event and in
+    /// [`JoyconInfo::product_string`](crate::JoyconInfo::product_string),
+    /// instead of the product name.
+    pub name: Option<String>,
+}
+
+/// Called with a joycon's stick values for every report it sends, at its full
+/// report rate (60 to 120 Hz) instead of once a frame, e.g. for recording
+/// input. Set with `Joycons::set_stick_callback`.
+///
+/// It runs on the joycon's polling thread, not the main thread (or during
+/// `update_joycon_data` with `JoyconsPlugin::immediate_mode`). The polling
+/// thread waits for it before reading the next report, so it should be cheap,
+/// e.g. pushing the sample into a channel.
+pub type StickCallback = Arc<dyn Fn(Gamepad, StickSample) + Send + Sync>;
+
+/// Stick values passed to a [`StickCallback`]. They're calibrated, zeroed and
+/// rotated like the values of gamepad events, but `JoyconSettings`' deadzones
+/// and filters aren't applied, since they depend on the frame rate.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct StickSample {
+    /// The physical left stick. Zero if the controller doesn't have one.
+    pub left: Vec2,
+    /// The physical right stick. Zero if the controller doesn't have one.
+    pub right: Vec2,
+    /// Timer byte of the report.
+    pub sequence: Option<u8>,
+    pub received_at: Instant,
+}
+
+/// User callbacks from the plugin's settings.
+#[derive(Resource, Default)]
+pub(crate) struct JoyconHooks {
+    pub(crate) report: Option<ReportHook>,
+    pub(crate) connection: Option<ConnectionPolicy>,
+}
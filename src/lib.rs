@@ -1,359 +1,3311 @@
+#[cfg(feature = "raw-subcommands")]
+use std::sync::mpsc::Receiver;
 use std::{
+    cmp::Reverse,
     sync::{
-        atomic::{AtomicUsize, Ordering},
-        Arc,
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        mpsc::{channel, Sender},
+        Arc, Mutex,
     },
     thread::spawn,
+    time::{Duration, Instant},
 };
 
 use anyhow::{Context, Result};
 use bevy_app::{App, CoreStage, Plugin};
 use bevy_ecs::{
     event::EventWriter,
-    schedule::IntoSystemDescriptor,
-    system::{NonSendMut, ResMut, Resource},
+    schedule::{IntoSystemDescriptor, SystemLabel},
+    system::{NonSendMut, Res, ResMut, Resource},
 };
 use bevy_input::{
-    gamepad::{Gamepad, GamepadAxisType, GamepadEventRaw, GamepadEventType, GamepadInfo},
+    gamepad::{Gamepad, GamepadAxisType, GamepadButtonType, Gamepads},
     InputSystem,
 };
+use bevy_math::Vec2;
+#[cfg(feature = "bevy_render")]
+use bevy_render::color::Color;
+use bevy_time::FixedTimestep;
 use bevy_utils::{
-    tracing::{error, info},
-    HashMap,
+    tracing::{error, info, info_span, warn},
+    HashMap, HashSet,
 };
 use joycon::{
-    hidapi::{DeviceInfo, HidApi},
+    hidapi::DeviceInfo,
     joycon_sys::{HID_IDS, NINTENDO_VENDOR_ID},
-    JoyCon as JoyconDevice, Report as JoyconReport,
+    JoyCon as JoyconDevice,
 };
 use pinboard::Pinboard;
-use thunderdome::{Arena, Index};
+use thread_priority::set_current_thread_priority;
+use thunderdome::Index;
 
+use crate::{
+    buttons::{assigned_stick_press, button_mapping, dpad_hat, Chord, Turbo},
+    calibration::{calibration_source, uncalibrated_stick_value, CalibrationAssistant},
+    device::{enable_rumble, DeviceCommand, DeviceControl},
+    gamepad_events::{GamepadEvents, RawGamepadEvent},
+    hooks::{JoyconHooks, StickCallback, StickSample},
+    motion::{average_accel, gyro_stick, held_layout, Steering},
+    stick::{apply_zero, StickFilters},
+    trackers::Trackers,
+};
+
+mod buttons;
+mod calibration;
+mod capabilities;
+#[cfg(feature = "debug-overlay")]
+mod debug_overlay;
+mod device;
+mod drift;
+mod events;
+mod gamepad_events;
+mod hooks;
+mod motion;
+mod persist;
+mod rumble;
+mod settings;
+mod snapshot;
+mod stick;
+mod trackers;
+
+pub use buttons::{ButtonMap, DpadMode, JoyconButton, JoyconLayout};
+pub use calibration::{CalibrationSource, CalibrationState, CustomCalibration};
+pub use capabilities::JoyconCapabilities;
+#[cfg(feature = "debug-overlay")]
+pub use debug_overlay::JoyconDebugPlugin;
+pub use device::player_light_pattern;
+pub use drift::{DriftHistory, DriftSample};
+pub use events::{
+    JoyconAxisChanged, JoyconCalibrationStateChanged, JoyconCalibrationWarning, JoyconIdle,
+    JoyconIdleDisconnected, JoyconRejected, JoyconSlotAssigned, JoyconWake,
+};
+pub use hooks::{
+    ConnectionDecision, ConnectionOptions, ConnectionPolicy, ConnectionRequest, ReportHook,
+    StickCallback, StickSample, REQUESTED_GAMEPAD_IDS,
+};
 pub use joycon::joycon_sys::{
-    input::{UseSPIColors, WhichController},
-    spi::ControllerColor,
+    input::{Battery, BatteryLevel, ButtonsStatus, UseSPIColors, WhichController},
+    spi::{Color as SpiColor, ControllerColor},
+};
+pub use joycon::{hidapi::HidApi, Report as JoyconReport, IMU};
+pub use motion::{ImuSample, ImuSamples, RelativePose};
+pub use persist::{JoyconPersistedSettings, PersistedControllerSettings};
+pub use rumble::{
+    RumbleEnvelope, RumbleRangePolicy, RumbleState, RUMBLE_AMPLITUDE_RANGE, RUMBLE_FREQUENCY_RANGE,
 };
+pub use settings::{AxisConvention, JoyconSettings, StickShape};
+pub use snapshot::JoyconSnapshot;
+pub use stick::{StickRotation, StickSide};
+pub use thread_priority::ThreadPriority;
 
 // We start at a really high number to avoid conflicting with gilrs.
 const STARTING_GAMEPAD_ID: usize = 0x8000_0000;
 
-#[derive(Default)]
-pub struct JoyconsPlugin;
+/// Player numbers are shown with the 4 player lights, which have a distinct
+/// pattern for each of the first 8 players.
+const MAX_PLAYER_NUMBER: u8 = 8;
+
+/// HID usages of the interface that joycons send their input on.
+const GENERIC_DESKTOP_USAGE_PAGE: u16 = 0x01;
+const JOYSTICK_USAGE: u16 = 0x04;
+const GAMEPAD_USAGE: u16 = 0x05;
+
+/// How close together a left and a right joycon need to connect for
+/// `Joycons::suggest_pairs` to suggest them as a pair.
+const PAIR_SUGGESTION_WINDOW: Duration = Duration::from_secs(2);
+
+/// Finds joycons and sends their input as gamepad events.
+///
+/// Its systems run in [`Self::STAGE`], and can be ordered against with the
+/// labels of [`JoyconSystem`].
+///
+/// By default, the plugin creates its own `HidApi`. To set it up differently,
+/// e.g. in sandboxed environments with restricted HID access, insert one as a
+/// non-send resource before adding the plugin, and it's used instead.
+pub struct JoyconsPlugin {
+    /// How long to wait between scans for connected and disconnected joycons
+    /// right after a joycon connected or disconnected, when more changes are
+    /// likely.
+    pub min_detection_interval: Duration,
+    /// The wait between scans doubles every time nothing changes, up to this
+    /// interval. Scanning can interfere with connected joycons on some
+    /// bluetooth stacks, so it shouldn't happen every frame.
+    pub max_detection_interval: Duration,
+    /// Called for every new report before it's processed, for custom
+    /// processing or logging.
+    pub report_hook: Option<ReportHook>,
+    /// Scheduling priority for the threads that poll each joycon, to keep
+    /// input latency low when other threads compete for the CPU. This is best
+    /// effort: raising the priority usually needs extra permissions, and if it
+    /// can't be set, a warning is logged and the default priority is used.
+    pub polling_thread_priority: Option<ThreadPriority>,
+    /// If true, newly connected joycons blink their lights and rumble briefly,
+    /// like `Joycons::identify`, to confirm the connection to the player.
+    /// Disabled by default.
+    pub identify_on_connect: bool,
+    /// The most joycons that may be connected at once. Others are left
+    /// alone, and a `JoyconRejected` event is sent for them. No limit by
+    /// default.
+    pub max_controllers: Option<usize>,
+    /// If true, every button press and release is logged at info level, to
+    /// quickly check that a controller works. Disabled by default.
+    pub log_buttons: bool,
+    /// If true, a joycon's first report is waited for on its polling thread,
+    /// and its gamepad only connects once that report arrives. Otherwise
+    /// (the default), it's waited for during detection, which can cause a
+    /// hitch when several joycons connect at once.
+    pub async_connect: bool,
+    /// Stops reconnecting joycons that connect too often, e.g. because of a
+    /// bad bluetooth link, so that games don't get a storm of connection
+    /// events. No limit by default.
+    pub reconnect_limit: Option<ReconnectLimit>,
+    /// If true, a `JoyconAxisChanged` event with the old and new values is
+    /// sent along with the gamepad event whenever an axis changes. Disabled
+    /// by default.
+    pub axis_change_events: bool,
+    /// If true, joycons connect with their IMU already enabled, so that motion
+    /// data is in the first report. It can still be disabled for each joycon
+    /// with [`Joycons::enable_imu`]. Disabled by default.
+    pub default_imu: bool,
+    /// If true, joycons are read in `update_joycon_data` on the main thread,
+    /// instead of on a polling thread each, so that every update uses a report
+    /// that was just read. Reading waits for the joycon's next report, which
+    /// can block each frame for up to a report interval (about 15ms) per
+    /// joycon, so this is only suitable for a single controller in
+    /// latency-sensitive games. Disabled by default.
+    pub immediate_mode: bool,
+    /// If set, gamepad events are sent on this fixed timestep instead of every
+    /// frame, e.g. to match a fixed-timestep simulation. Each step handles the
+    /// latest report, which polling threads keep reading at full rate. It uses
+    /// Bevy's `FixedTimestep`, so it needs the `Time` resource. `None` by
+    /// default.
+    pub event_timestep: Option<Duration>,
+    /// When every connected joycon disappears at once, it's more likely that
+    /// the app briefly lost access to HID devices, e.g. when resuming from
+    /// suspend, than that they were all turned off. Their gamepads then stay
+    /// connected, with their input released, for this long. Joycons that come
+    /// back in time keep their gamepad, and the rest are disconnected. Only
+    /// applies when at least 2 joycons were connected, since a single one
+    /// disappearing looks the same either way. Defaults to 3 seconds. `None`
+    /// disconnects them right away.
+    pub hid_loss_grace: Option<Duration>,
+    /// Decides whether to open each joycon that's detected, and with which
+    /// gamepad and name. All joycons are opened by default.
+    pub connection_policy: Option<ConnectionPolicy>,
+    /// If set, joycons that had no input for this long are freed for the next
+    /// player, as chosen by `idle_disconnect_action`, and a
+    /// `JoyconIdleDisconnected` event is sent, e.g. for public installations.
+    /// This is separate from `JoyconSettings::idle_timeout`. `None` by
+    /// default.
+    pub idle_disconnect: Option<Duration>,
+    /// What happens to a joycon after `idle_disconnect`. Defaults to
+    /// [`IdleDisconnectAction::Release`].
+    pub idle_disconnect_action: IdleDisconnectAction,
+    /// What `Joycons::play_rumble_envelope` does with frequencies and
+    /// amplitudes that joycons don't support. Clamps them by default.
+    pub rumble_range_policy: RumbleRangePolicy,
+}
+
+impl JoyconsPlugin {
+    /// The stage that all of the plugin's systems are added to.
+    pub const STAGE: CoreStage = CoreStage::PreUpdate;
+}
+
+/// Labels of the systems added by [`JoyconsPlugin`], to run other systems
+/// before or after them. They run in the order they're listed in, and before
+/// Bevy's `InputSystem`, so that joycon input is handled in the same frame.
+#[derive(SystemLabel, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum JoyconSystem {
+    /// Reopens joycons that stopped sending reports, as set by
+    /// `JoyconSettings::report_timeout`.
+    WatchPollingThreads,
+    /// Scans for joycons that connected or disconnected, and opens new ones.
+    /// Most runs return right away, because scans are spaced out.
+    DetectConnections,
+    /// Sends gamepad events for the latest reports, and updates everything
+    /// that `Joycons` returns about them. With
+    /// `JoyconsPlugin::event_timestep`, this only runs on that timestep.
+    UpdateJoyconData,
+}
+
+/// What happens to a joycon after `JoyconsPlugin::idle_disconnect`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum IdleDisconnectAction {
+    /// It's released, as with `Joycons::release`, until it's reacquired. Its
+    /// reports are still read, so the game can watch `Joycons::snapshot` or
+    /// `JoyconWake` and call `Joycons::reacquire` once someone picks it up.
+    #[default]
+    Release,
+    /// It's released, and told to disconnect from bluetooth, which puts it to
+    /// sleep. Its gamepad disconnects once it's gone, and pressing one of its
+    /// buttons connects it again with a new gamepad. Controllers that don't
+    /// disconnect, e.g. over USB, stay released.
+    Disconnect,
+}
+
+/// A joycon that connected `max_connections` times within `window` is left
+/// alone, with a warning, until it connected fewer times than that within the
+/// last `window`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ReconnectLimit {
+    pub max_connections: usize,
+    pub window: Duration,
+}
+
+impl Default for JoyconsPlugin {
+    fn default() -> Self {
+        Self {
+            min_detection_interval: Duration::from_millis(50),
+            max_detection_interval: Duration::from_secs(1),
+            report_hook: None,
+            polling_thread_priority: None,
+            identify_on_connect: false,
+            max_controllers: None,
+            log_buttons: false,
+            async_connect: false,
+            reconnect_limit: None,
+            axis_change_events: false,
+            default_imu: false,
+            immediate_mode: false,
+            event_timestep: None,
+            hid_loss_grace: Some(Duration::from_secs(3)),
+            connection_policy: None,
+            idle_disconnect: None,
+            idle_disconnect_action: IdleDisconnectAction::Release,
+            rumble_range_policy: RumbleRangePolicy::Clamp,
+        }
+    }
+}
 
 impl Plugin for JoyconsPlugin {
     fn build(&self, app: &mut App) {
-        let hidapi = match HidApi::new_without_enumerate() {
-            Ok(x) => x,
-            Err(e) => {
-                error!("Failed to setup HidApi: {}", e);
-                return;
-            }
-        };
+        if app.world.get_non_send_resource::<HidApi>().is_none() {
+            let hidapi = match HidApi::new_without_enumerate() {
+                Ok(x) => x,
+                Err(e) => {
+                    error!("Failed to setup HidApi: {}", e);
+                    return;
+                }
+            };
+            app.insert_non_send_resource(hidapi);
+        }
 
-        app.insert_non_send_resource(hidapi)
-            .insert_resource(Joycons::new())
+        app.insert_resource(Joycons::new(self))
+            .insert_resource(DetectionSchedule::new(
+                self.min_detection_interval,
+                self.max_detection_interval,
+            ))
+            .init_resource::<JoyconSettings>()
+            .insert_resource(JoyconHooks {
+                report: self.report_hook.clone(),
+                connection: self.connection_policy.clone(),
+            })
+            .add_event::<JoyconAxisChanged>()
+            .add_event::<JoyconCalibrationStateChanged>()
+            .add_event::<JoyconCalibrationWarning>()
+            .add_event::<JoyconIdle>()
+            .add_event::<JoyconIdleDisconnected>()
+            .add_event::<JoyconRejected>()
+            .add_event::<JoyconSlotAssigned>()
+            .add_event::<JoyconWake>()
             .add_system_to_stage(
-                CoreStage::PreUpdate,
-                detect_connection_changes.before(InputSystem),
-            )
-            .add_system_to_stage(
-                CoreStage::PreUpdate,
-                update_joycon_data
-                    .after(detect_connection_changes)
-                    .before(InputSystem),
+                Self::STAGE,
+                watch_polling_threads
+                    .label(JoyconSystem::WatchPollingThreads)
+                    .before(JoyconSystem::DetectConnections),
             );
+
+        let detect = detect_connection_changes.label(JoyconSystem::DetectConnections);
+        let mut update = update_joycon_data
+            .label(JoyconSystem::UpdateJoyconData)
+            .after(JoyconSystem::DetectConnections);
+        if let Some(timestep) = self.event_timestep {
+            update = update.with_run_criteria(FixedTimestep::step(timestep.as_secs_f64()));
+        }
+
+        // Without Bevy's input plugin, e.g. in headless apps, the gamepad
+        // events are still sent, so that they can be read directly. Adding the
+        // event again is a no-op if the input plugin already did, whichever
+        // order the plugins are added in.
+        app.add_event::<RawGamepadEvent>()
+            .add_system_to_stage(Self::STAGE, detect.before(InputSystem))
+            .add_system_to_stage(Self::STAGE, update.before(InputSystem));
     }
 }
 
 #[derive(Resource)]
 pub struct Joycons {
-    trackers: Arena<Tracker>,
+    trackers: Trackers<Tracker>,
     joycons_by_serial_number: HashMap<String, Result<Index, ()>>,
-    joycons_by_gamepad: HashMap<Gamepad, Index>,
     next_gamepad_id: AtomicUsize,
+    /// Number of polling threads that haven't exited yet.
+    polling_threads: Arc<AtomicUsize>,
+    events_last_update: usize,
+    /// The value of `JoyconSettings::input_enabled` as of the last update.
+    input_enabled: bool,
+    polling_thread_priority: Option<ThreadPriority>,
+    identify_on_connect: bool,
+    max_controllers: Option<usize>,
+    /// Joycons that were left alone because of `max_controllers`, so that
+    /// `JoyconRejected` is only sent once for each.
+    rejected_serial_numbers: HashSet<String>,
+    /// Joycons rejected by `JoyconsPlugin::connection_policy`, so that it's
+    /// only asked once about each.
+    vetoed_serial_numbers: HashSet<String>,
+    log_buttons: bool,
+    async_connect: bool,
+    reconnect_limit: Option<ReconnectLimit>,
+    axis_change_events: bool,
+    default_imu: bool,
+    immediate_mode: bool,
+    hid_loss_grace: Option<Duration>,
+    rumble_range_policy: RumbleRangePolicy,
+    idle_disconnect: Option<Duration>,
+    idle_disconnect_action: IdleDisconnectAction,
+    /// Gamepads of joycons that disappeared together with all the others,
+    /// kept for `hid_loss_grace` in case they come back, and when that
+    /// happened.
+    held_gamepads: HashMap<String, Gamepad>,
+    hid_lost_at: Option<Instant>,
+    /// When each joycon connected recently, for `reconnect_limit`.
+    connection_times: HashMap<String, Vec<Instant>>,
+    /// Joycons left alone because of `reconnect_limit`, so that the warning
+    /// is only logged once.
+    unstable_serial_numbers: HashSet<String>,
+    /// Runtime settings of joycons that disconnected, by serial number, to be
+    /// re-applied if they reconnect.
+    saved_settings: HashMap<String, RuntimeSettings>,
+    /// Settings from `import_settings` for joycons that weren't connected, by
+    /// serial number, applied on top of their other settings when they
+    /// connect.
+    imported_settings: HashMap<String, PersistedControllerSettings>,
+    /// When the list of HID devices was last refreshed successfully.
+    last_refresh: Option<Instant>,
+    /// Errors that stopped the polling of joycons that were then
+    /// disconnected, as `(serial number, error)`. Forgotten once the joycon
+    /// connects again.
+    poll_errors: HashMap<Gamepad, (String, String)>,
+    /// Mappings that replace the built-in ones, set by
+    /// `Joycons::set_button_map`.
+    button_maps: Vec<(WhichController, ButtonMap)>,
+    /// Player slots reserved with `Joycons::reserve_slots`, and the gamepad
+    /// in each.
+    slots: Vec<Option<Gamepad>>,
+}
+
+/// Counters describing how much work the plugin is doing, for profiling setups
+/// with many controllers.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct JoyconStats {
+    /// Number of joycons currently being tracked.
+    pub tracked_controllers: usize,
+    /// Number of polling threads that are currently running. Each tracked
+    /// joycon has one, unless its thread exited due to an error or it's read
+    /// in immediate mode.
+    pub polling_threads: usize,
+    /// Number of gamepad events sent by the last run of the update system.
+    pub events_last_update: usize,
 }
 
 impl Joycons {
-    fn new() -> Self {
+    fn new(plugin: &JoyconsPlugin) -> Self {
         Self {
-            trackers: Arena::new(),
+            trackers: Trackers::new(),
             joycons_by_serial_number: HashMap::new(),
-            joycons_by_gamepad: HashMap::new(),
             next_gamepad_id: AtomicUsize::new(STARTING_GAMEPAD_ID),
+            polling_threads: Arc::new(AtomicUsize::new(0)),
+            events_last_update: 0,
+            input_enabled: true,
+            polling_thread_priority: plugin.polling_thread_priority,
+            identify_on_connect: plugin.identify_on_connect,
+            max_controllers: plugin.max_controllers,
+            rejected_serial_numbers: HashSet::new(),
+            vetoed_serial_numbers: HashSet::new(),
+            log_buttons: plugin.log_buttons,
+            async_connect: plugin.async_connect,
+            reconnect_limit: plugin.reconnect_limit,
+            axis_change_events: plugin.axis_change_events,
+            default_imu: plugin.default_imu,
+            immediate_mode: plugin.immediate_mode,
+            hid_loss_grace: plugin.hid_loss_grace,
+            rumble_range_policy: plugin.rumble_range_policy,
+            idle_disconnect: plugin.idle_disconnect,
+            idle_disconnect_action: plugin.idle_disconnect_action,
+            held_gamepads: HashMap::new(),
+            hid_lost_at: None,
+            connection_times: HashMap::new(),
+            unstable_serial_numbers: HashSet::new(),
+            saved_settings: HashMap::new(),
+            imported_settings: HashMap::new(),
+            last_refresh: None,
+            poll_errors: HashMap::new(),
+            button_maps: Vec::new(),
+            slots: Vec::new(),
         }
     }
 
-    pub fn get_info(&self, gamepad: Gamepad) -> Option<&JoyconInfo> {
-        let index = self.joycons_by_gamepad.get(&gamepad)?;
-        let tracker = self.trackers.get(*index)?;
-        Some(&tracker.info)
+    pub fn stats(&self) -> JoyconStats {
+        JoyconStats {
+            tracked_controllers: self.trackers.len(),
+            polling_threads: self.polling_threads.load(Ordering::SeqCst),
+            events_last_update: self.events_last_update,
+        }
     }
-}
 
-fn detect_connection_changes(
-    mut hidapi: NonSendMut<HidApi>,
-    mut joycons: ResMut<Joycons>,
-    mut events: EventWriter<GamepadEventRaw>,
-) {
-    if let Err(e) = detect_connection_changes_inner(&mut hidapi, &mut joycons, &mut events) {
-        error!("Error detecting joycon connections/disconnections: {}", e);
+    /// Returns how long ago the list of connected devices was last refreshed
+    /// successfully, or `None` if it never was. Scans are spaced out while
+    /// nothing changes, so this normally stays below the plugin's
+    /// `max_detection_interval`. If it grows past that, detection is failing.
+    pub fn last_refresh_age(&self) -> Option<Duration> {
+        Some(self.last_refresh?.elapsed())
     }
-}
 
-fn detect_connection_changes_inner(
-    hidapi: &mut HidApi,
-    joycons: &mut Joycons,
-    events: &mut EventWriter<GamepadEventRaw>,
-) -> Result<()> {
-    hidapi
-        .refresh_devices()
-        .context("Refreshing hidapi device list")?;
+    pub fn any_connected(&self) -> bool {
+        !self.trackers.is_empty()
+    }
 
-    for device_info in hidapi.device_list() {
-        if !is_joycon_device(device_info) {
-            continue;
+    pub fn connected_count(&self) -> usize {
+        self.trackers.len()
+    }
+
+    /// Returns the controller's body color as `[r, g, b]`, or `None` if the
+    /// gamepad isn't a known joycon or its colors couldn't be read.
+    pub fn gamepad_color_rgb(&self, gamepad: Gamepad) -> Option<[u8; 3]> {
+        let info = self.get_info(gamepad).filter(|info| info.color_available)?;
+        let color = info.color.body;
+        Some([color.r, color.g, color.b])
+    }
+
+    /// Returns the controller's body color, e.g. to tint things belonging to
+    /// its player. White if the gamepad isn't a known joycon or its colors
+    /// couldn't be read.
+    #[cfg(feature = "bevy_render")]
+    pub fn gamepad_color(&self, gamepad: Gamepad) -> Color {
+        self.gamepad_color_rgb(gamepad)
+            .map_or(Color::WHITE, |[r, g, b]| Color::rgb_u8(r, g, b))
+    }
+
+    /// Returns whether one of the gamepad's sticks is pushed away from the
+    /// center, after all processing including the deadzone. It counts as
+    /// pushed once it's further than `JoyconSettings::idle_stick_threshold`,
+    /// the same as for idle detection. Returns false if the gamepad isn't a
+    /// known joycon.
+    pub fn stick_active(&self, gamepad: Gamepad, side: StickSide) -> bool {
+        self.tracker(gamepad)
+            .map_or(false, |tracker| side.select(tracker.active_sticks))
+    }
+
+    /// Returns the controller that most recently had input, for games that let
+    /// any controller play and want to follow whichever one is in use. Input
+    /// counts the same way as for idle detection, so stick drift below
+    /// `JoyconSettings::idle_stick_threshold` doesn't make a controller
+    /// primary. Released controllers and controllers that haven't had any input
+    /// since connecting are never primary.
+    pub fn primary_gamepad(&self) -> Option<Gamepad> {
+        self.trackers
+            .iter()
+            .filter(|(_, tracker)| tracker.had_input && !tracker.released)
+            .max_by_key(|(_, tracker)| tracker.last_input)
+            .map(|(_, tracker)| tracker.gamepad)
+    }
+
+    /// Returns how many controllers of the given type are connected.
+    pub fn connected_count_of(&self, which: WhichController) -> usize {
+        self.iter_by_type(which).count()
+    }
+
+    /// Iterates over the connected controllers, in no particular order.
+    pub fn iter(&self) -> impl Iterator<Item = (Gamepad, &JoyconInfo)> {
+        self.trackers
+            .iter()
+            .map(|(_, tracker)| (tracker.gamepad, &tracker.info))
+    }
+
+    /// Iterates over the connected controllers of the given type, in no
+    /// particular order.
+    pub fn iter_by_type(
+        &self,
+        which: WhichController,
+    ) -> impl Iterator<Item = (Gamepad, &JoyconInfo)> {
+        self.iter().filter(move |(_, info)| info.which == which)
+    }
+
+    /// Suggests which left and right joycons could be used together as one
+    /// controller, as `(left, right)`. There's no way to tell which joycons
+    /// belong to the same set, so this pairs the ones that connected within 2
+    /// seconds of each other, e.g. after being detached from the same console
+    /// or grip, closest first. Each joycon is in at most one pair.
+    pub fn suggest_pairs(&self) -> Vec<(Gamepad, Gamepad)> {
+        let mut candidates = Vec::new();
+        for (left, left_info) in self.iter_by_type(WhichController::LeftJoyCon) {
+            for (right, right_info) in self.iter_by_type(WhichController::RightJoyCon) {
+                let gap = if left_info.connected_at > right_info.connected_at {
+                    left_info.connected_at - right_info.connected_at
+                } else {
+                    right_info.connected_at - left_info.connected_at
+                };
+                if gap <= PAIR_SUGGESTION_WINDOW {
+                    candidates.push((gap, left, right));
+                }
+            }
         }
+        candidates.sort_by_key(|(gap, left, right)| (*gap, left.id, right.id));
 
-        let Some(serial_num) = device_info.serial_number() else {
-            error!("Bad joycon serial number");
-            continue;
-        };
+        let mut paired = HashSet::new();
+        let mut pairs = Vec::new();
+        for (_, left, right) in candidates {
+            if !paired.contains(&left) && !paired.contains(&right) {
+                paired.insert(left);
+                paired.insert(right);
+                pairs.push((left, right));
+            }
+        }
+        pairs
+    }
 
-        if joycons.joycons_by_serial_number.contains_key(serial_num) {
-            continue;
+    pub fn get_info(&self, gamepad: Gamepad) -> Option<&JoyconInfo> {
+        Some(&self.tracker(gamepad)?.info)
+    }
+
+    /// Returns the timer byte of the last report that was processed for this
+    /// gamepad. The joycon increments it for every report it sends, so it can
+    /// be used to detect stale reports or to interpolate between them.
+    pub fn get_report_sequence(&self, gamepad: Gamepad) -> Option<u8> {
+        self.tracker(gamepad)?.last_sequence
+    }
+
+    /// Stops sending input events for this gamepad, without disconnecting it.
+    /// Its sticks are reported as centered once, so that it stops driving
+    /// whatever it was controlling. The controller stays connected and its
+    /// info remains available, so that it can rejoin later with
+    /// [`Self::reacquire`]. Its reports are still read, so [`Self::snapshot`]
+    /// and `JoyconWake` can be used to tell when someone picks it up.
+    ///
+    /// Returns false if the gamepad isn't a known joycon.
+    pub fn release(&mut self, gamepad: Gamepad) -> bool {
+        let Some(tracker) = self.tracker_mut(gamepad) else { return false };
+        tracker.release();
+        true
+    }
+
+    /// Resumes sending input events for a gamepad that was released with
+    /// [`Self::release`]. The controller's current state is sent on the next
+    /// update, even if it hasn't changed since it was released.
+    ///
+    /// Returns false if the gamepad isn't a known joycon.
+    pub fn reacquire(&mut self, gamepad: Gamepad) -> bool {
+        let Some(tracker) = self.tracker_mut(gamepad) else { return false };
+        if tracker.released {
+            tracker.released = false;
+            tracker.resume();
         }
+        true
+    }
 
-        let Some(product_string) = device_info.product_string() else {
-            error!("Bad product string for joycon {}", serial_num);
-            continue;
-        };
+    /// Sets how this gamepad's sticks are rotated. Single joycons default to
+    /// being rotated by 90 degrees to match being held sideways, while pro
+    /// controllers aren't rotated.
+    ///
+    /// Returns false if the gamepad isn't a known joycon.
+    pub fn set_stick_rotation(&mut self, gamepad: Gamepad, rotation: StickRotation) -> bool {
+        let Some(tracker) = self.tracker_mut(gamepad) else { return false };
+        tracker.rotation = rotation;
+        true
+    }
 
-        let gamepad = Gamepad {
-            id: joycons.next_gamepad_id.fetch_add(1, Ordering::SeqCst),
-        };
-        let index = match Tracker::new(hidapi, device_info, gamepad) {
-            Ok((joycon_device, tracker)) => {
-                info!("'{}' ({}) connected", product_string, serial_num);
+    pub fn get_stick_rotation(&self, gamepad: Gamepad) -> Option<StickRotation> {
+        Some(self.tracker(gamepad)?.rotation)
+    }
 
-                events.send(GamepadEventRaw {
-                    gamepad,
-                    event_type: GamepadEventType::Connected(GamepadInfo {
-                        name: product_string.to_string(),
-                    }),
-                });
+    /// Returns the full state of the gamepad as of the last update, so that all
+    /// of its inputs can be sampled at once instead of reacting to events.
+    pub fn snapshot(&self, gamepad: Gamepad) -> Option<JoyconSnapshot> {
+        self.tracker(gamepad)?.snapshot.clone()
+    }
 
-                // This needs a dedicated thread, otherwise we get (more?)
-                // latency.
-                spawn({
-                    let product_string = tracker.info.product_string.clone();
-                    let serial_number = tracker.info.serial_number.clone();
-                    let last_report = tracker.last_report.clone();
-
-                    move || {
-                        joycon_polling_thread(
-                            joycon_device,
-                            product_string,
-                            serial_number,
-                            last_report,
-                        );
-                    }
-                });
+    /// Returns the snapshots of all joycons that have been updated at least
+    /// once, ordered by gamepad. They're all from the same update, so reading
+    /// them together gives a consistent view of every player's input, even
+    /// though the underlying reports arrive independently.
+    pub fn snapshot_all(&self) -> Vec<(Gamepad, JoyconSnapshot)> {
+        let mut snapshots = self
+            .trackers
+            .iter()
+            .filter_map(|(_, tracker)| Some((tracker.gamepad, tracker.snapshot.clone()?)))
+            .collect::<Vec<_>>();
+        snapshots.sort_by_key(|(gamepad, _)| gamepad.id);
+        snapshots
+    }
 
-                let index = joycons.trackers.insert(tracker);
+    /// Returns the serial numbers of joycons that are present but failed to
+    /// open. They aren't retried until they disconnect.
+    pub fn failed_serials(&self) -> Vec<String> {
+        let mut serial_numbers = self
+            .joycons_by_serial_number
+            .iter()
+            .filter(|(_, index)| index.is_err())
+            .map(|(serial_number, _)| serial_number.clone())
+            .collect::<Vec<_>>();
+        serial_numbers.sort();
+        serial_numbers
+    }
 
-                joycons.joycons_by_gamepad.insert(gamepad, index);
+    /// Makes a button auto-fire while it's held: it's reported as pressed and
+    /// released `hz` times a second. A non-positive `hz` disables auto-fire,
+    /// like [`Self::clear_turbo`].
+    ///
+    /// Returns false if the gamepad isn't a known joycon.
+    pub fn set_turbo(&mut self, gamepad: Gamepad, button: GamepadButtonType, hz: f32) -> bool {
+        let Some(tracker) = self.tracker_mut(gamepad) else { return false };
+        if hz > 0.0 {
+            tracker.turbo.insert(button, Turbo::new(hz));
+        } else {
+            tracker.turbo.remove(&button);
+        }
+        true
+    }
 
-                Ok(index)
-            }
+    /// Disables auto-fire for a button. If it's still held, it's reported as
+    /// pressed on the next update.
+    ///
+    /// Returns false if the gamepad isn't a known joycon.
+    pub fn clear_turbo(&mut self, gamepad: Gamepad, button: GamepadButtonType) -> bool {
+        self.set_turbo(gamepad, button, 0.0)
+    }
 
-            Err(e) => {
-                error!("Error opening '{}' ({}): {}", product_string, serial_num, e);
-                // Remember that we had an error, so that we don't retry every
-                // frame.
-                Err(())
-            }
+    /// Reports `button` as pressed while all of `buttons` are held, e.g. SL and
+    /// SR together as a modifier. If `suppress` is true, the chord's buttons
+    /// aren't reported by themselves while it's held, nor until they're
+    /// released after it breaks, but one that's pressed before the others is
+    /// still reported until the chord is complete. `button` shouldn't be one
+    /// that the controller's buttons are mapped to, e.g. use
+    /// `GamepadButtonType::Other`. Mapping the same `button` again replaces its
+    /// chord.
+    ///
+    /// Returns false if the gamepad isn't a known joycon.
+    pub fn map_chord(
+        &mut self,
+        gamepad: Gamepad,
+        buttons: &[JoyconButton],
+        button: GamepadButtonType,
+        suppress: bool,
+    ) -> bool {
+        let Some(tracker) = self.tracker_mut(gamepad) else { return false };
+        tracker.chords.retain(|chord| chord.button != button);
+        tracker.chords.push(Chord {
+            buttons: buttons.to_vec(),
+            button,
+            suppress,
+        });
+        true
+    }
+
+    /// Removes the chord that was mapped to `button` with [`Self::map_chord`].
+    /// If it's held, it's reported as released on the next update.
+    ///
+    /// Returns false if the gamepad isn't a known joycon.
+    pub fn unmap_chord(&mut self, gamepad: Gamepad, button: GamepadButtonType) -> bool {
+        let Some(tracker) = self.tracker_mut(gamepad) else { return false };
+        tracker.chords.retain(|chord| chord.button != button);
+        true
+    }
+
+    /// Returns the value of one of the gamepad's sticks as of the last update,
+    /// after rotation and the other processing from [`JoyconSettings`]. This is
+    /// the value that was sent in gamepad events. Single joycons report their
+    /// stick as the left stick.
+    pub fn get_stick(&self, gamepad: Gamepad, side: StickSide) -> Option<Vec2> {
+        let snapshot = self.tracker(gamepad)?.snapshot.as_ref()?;
+        Some(side.select([snapshot.left_stick, snapshot.right_stick]))
+    }
+
+    /// Formats the controller's identity and its state as of the last update
+    /// in a human-readable block, e.g. for pasting into bug reports. Returns
+    /// `None` if the gamepad isn't a known joycon.
+    pub fn debug_dump(&self, gamepad: Gamepad) -> Option<String> {
+        let tracker = self.tracker(gamepad)?;
+        let info = &tracker.info;
+        let state = match &tracker.snapshot {
+            Some(snapshot) => snapshot.dump(),
+            None => "no report yet\n".to_string(),
         };
 
-        joycons
-            .joycons_by_serial_number
-            .insert(serial_num.to_string(), index);
+        Some(format!(
+            "'{}' ({}), {:?}\n{}",
+            info.product_string, info.serial_number, info.which, state
+        ))
     }
 
-    Ok(())
-}
+    /// Returns the value of one of the controller's physical sticks as of the
+    /// last update, as the hardware reports it, without any rotation or other
+    /// processing. Returns `None` if the controller doesn't have that stick.
+    pub fn get_stick_native(&self, gamepad: Gamepad, side: StickSide) -> Option<Vec2> {
+        let tracker = self.tracker(gamepad)?;
+        if !StickSide::physical_sticks(tracker.info.which).contains(&side) {
+            return None;
+        }
 
-fn is_joycon_device(device_info: &DeviceInfo) -> bool {
-    device_info.vendor_id() == NINTENDO_VENDOR_ID && HID_IDS.contains(&device_info.product_id())
-}
+        let [x, y] = side.select(tracker.native_sticks?);
+        Some(Vec2::new(x as f32, y as f32))
+    }
 
-pub struct JoyconInfo {
-    pub product_string: String,
-    pub serial_number: String,
-    pub which: WhichController,
-    pub color: ControllerColor,
-    pub use_spi_colors: UseSPIColors,
-}
+    /// Returns how much one of the controller's physical sticks drifted over
+    /// time while resting, as a hardware health check. The history starts over
+    /// when the controller reconnects. Returns `None` if the gamepad isn't a
+    /// known joycon or doesn't have that stick.
+    pub fn drift_history(&self, gamepad: Gamepad, side: StickSide) -> Option<&DriftHistory> {
+        let tracker = self.tracker(gamepad)?;
+        if !StickSide::physical_sticks(tracker.info.which).contains(&side) {
+            return None;
+        }
 
-impl JoyconInfo {
-    fn new(device_info: &DeviceInfo, joycon_device: &mut JoyconDevice) -> Result<Self> {
-        let product_string = device_info.product_string().unwrap().to_string();
-        let serial_number = device_info.serial_number().unwrap().to_string();
+        Some(side.select(&tracker.drift))
+    }
 
-        let joycon_dev_info = joycon_device
-            .get_dev_info()
-            .context("Getting joycon device info")?;
-        let which = joycon_dev_info
-            .which_controller
-            .try_into()
-            .context("Parsing joycon type")?;
-        let use_spi_colors = joycon_dev_info
-            .use_spi_colors
-            .try_into()
-            .context("Parsing joycon UseSPIColors data")?;
+    /// Returns the raw 12-bit values of one of the controller's physical sticks
+    /// as of the last update, as `(x, y)`, before calibration or any other
+    /// processing. Returns `None` if the controller doesn't have that stick.
+    pub fn get_stick_raw_adc(&self, gamepad: Gamepad, side: StickSide) -> Option<(u16, u16)> {
+        let tracker = self.tracker(gamepad)?;
+        if !StickSide::physical_sticks(tracker.info.which).contains(&side) {
+            return None;
+        }
 
-        let color = joycon_device
-            .read_spi()
-            .context("Reading controller color")?;
+        Some(side.select(tracker.raw_sticks?))
+    }
 
-        Ok(Self {
-            product_string,
-            serial_number,
-            which,
-            use_spi_colors,
-            color,
-        })
+    /// Makes the current position of one of the controller's physical sticks
+    /// its new center, to compensate for a stick that rests slightly off
+    /// center. The offset is subtracted from the stick's native values before
+    /// any other processing.
+    ///
+    /// Returns false if the gamepad isn't a known joycon, doesn't have that
+    /// stick, or hasn't been updated yet.
+    pub fn zero_stick(&mut self, gamepad: Gamepad, side: StickSide) -> bool {
+        let Some(tracker) = self.tracker_mut(gamepad) else { return false };
+        if !StickSide::physical_sticks(tracker.info.which).contains(&side) {
+            return false;
+        }
+        let Some(native_sticks) = tracker.native_sticks else { return false };
+
+        *side.select_mut(&mut tracker.stick_zero) = side.select(native_sticks);
+        true
     }
-}
 
-struct Tracker {
-    info: JoyconInfo,
-    /// If the pinboard is empty, then the joycon thread has hit an error.
-    last_report: Arc<Pinboard<JoyconReport>>,
-    gamepad: Gamepad,
-}
+    /// Starts a guided calibration of one of the controller's physical sticks,
+    /// for a calibration screen. The player goes through the steps of
+    /// [`CalibrationState`], and a [`JoyconCalibrationStateChanged`] event is
+    /// sent for each step after the first. Once it's done, the new calibration
+    /// replaces the one the controller came with, and it's kept if the joycon
+    /// reconnects. Starting again starts over.
+    ///
+    /// Returns false if the gamepad isn't a known joycon, or doesn't have that
+    /// stick.
+    pub fn start_calibration(&mut self, gamepad: Gamepad, side: StickSide) -> bool {
+        let Some(tracker) = self.tracker_mut(gamepad) else { return false };
+        if !StickSide::physical_sticks(tracker.info.which).contains(&side) {
+            return false;
+        }
+        tracker.calibration = Some(CalibrationAssistant::new(side));
+        true
+    }
 
-impl Tracker {
-    fn new(
-        hidapi: &HidApi,
-        device_info: &DeviceInfo,
+    /// Returns the current step of the gamepad's guided calibration, or `None`
+    /// if none was started or it was cancelled. Stays at
+    /// [`CalibrationState::Done`] once it's done.
+    pub fn calibration_state(&self, gamepad: Gamepad) -> Option<CalibrationState> {
+        Some(self.tracker(gamepad)?.calibration.as_ref()?.state)
+    }
+
+    /// Stops a guided calibration, keeping the calibration that was used
+    /// before. Returns false if the gamepad isn't a known joycon.
+    pub fn cancel_calibration(&mut self, gamepad: Gamepad) -> bool {
+        let Some(tracker) = self.tracker_mut(gamepad) else { return false };
+        tracker.calibration = None;
+        true
+    }
+
+    /// Goes back to the controller's own calibration for a stick, after a
+    /// guided calibration. Returns false if the gamepad isn't a known joycon.
+    pub fn clear_calibration(&mut self, gamepad: Gamepad, side: StickSide) -> bool {
+        let Some(tracker) = self.tracker_mut(gamepad) else { return false };
+        *side.select_mut(&mut tracker.custom_calibration) = None;
+        true
+    }
+
+    /// Calls `callback` for every report of the gamepad, on its polling thread,
+    /// with its stick values. See [`StickCallback`] for what the callback
+    /// should and shouldn't do. `None` removes the callback. It's removed when
+    /// the joycon disconnects.
+    ///
+    /// Returns false if the gamepad isn't a known joycon.
+    pub fn set_stick_callback(
+        &mut self,
         gamepad: Gamepad,
-    ) -> Result<(JoyconDevice, Self)> {
-        let device = device_info
-            .open_device(hidapi)
-            .context("Opening joycon hid device")?;
-        let mut joycon_device =
-            JoyconDevice::new(device, device_info.clone()).context("Initializing joycon")?;
+        callback: Option<StickCallback>,
+    ) -> bool {
+        let Some(tracker) = self.tracker_mut(gamepad) else { return false };
+        *tracker.stick_stream.lock().unwrap() = callback.map(|callback| StickStream {
+            callback,
+            gamepad,
+            which: tracker.info.which,
+            calibration_valid: tracker.calibration_valid,
+            custom_calibration: tracker.custom_calibration,
+            stick_zero: tracker.stick_zero,
+            rotation: tracker.rotation,
+            // Synced with the settings on the next update.
+            axis_convention: AxisConvention::default(),
+        });
+        true
+    }
 
-        joycon_device
-            .load_calibration()
-            .context("Loading calibration data")?;
+    /// Undoes `zero_stick`. Returns false if the gamepad isn't a known joycon.
+    pub fn clear_zero(&mut self, gamepad: Gamepad, side: StickSide) -> bool {
+        let Some(tracker) = self.tracker_mut(gamepad) else { return false };
+        *side.select_mut(&mut tracker.stick_zero) = [0.0, 0.0];
+        true
+    }
 
-        let info = JoyconInfo::new(device_info, &mut joycon_device)?;
+    /// Drives a stick that the controller doesn't physically have with its
+    /// gyro, e.g. so that a player using a single joycon can still control a
+    /// camera with the right stick. `sensitivity` is how far the stick is
+    /// pushed per degree per second of rotation, and can be negative to
+    /// invert the stick. Requires the IMU to be enabled with `enable_imu`.
+    ///
+    /// Returns false if the gamepad isn't a known joycon, or if the stick is
+    /// already driven by one of its physical sticks.
+    pub fn set_gyro_stick(&mut self, gamepad: Gamepad, stick: StickSide, sensitivity: f64) -> bool {
+        let Some(tracker) = self.tracker_mut(gamepad) else { return false };
+        if StickSide::physical_sticks(tracker.info.which)
+            .iter()
+            .any(|&side| tracker.output_stick(side) == stick)
+        {
+            return false;
+        }
 
-        let report = joycon_device.tick().context("Polling joycon first time")?;
-        let last_report = Arc::new(Pinboard::new(report));
+        tracker.gyro_stick = Some((stick, sensitivity));
+        true
+    }
 
-        Ok((
-            joycon_device,
-            Self {
-                info,
-                last_report,
-                gamepad,
-            },
-        ))
+    /// Stops driving a stick with the gyro, and centers it on the next update.
+    /// Returns false if the gamepad isn't a known joycon.
+    pub fn clear_gyro_stick(&mut self, gamepad: Gamepad) -> bool {
+        let Some(tracker) = self.tracker_mut(gamepad) else { return false };
+        if let Some((output, _)) = tracker.gyro_stick.take() {
+            tracker.stale_sticks.push(output);
+        }
+        true
+    }
+
+    /// Sets which gamepad stick a single joycon's stick is reported as. By
+    /// default it's the left stick, even for right joycons, so that each
+    /// player holding a single joycon uses the same stick. Has no effect on
+    /// pro controllers.
+    ///
+    /// Returns false if the gamepad isn't a known joycon, or if the stick is
+    /// already driven by the gyro.
+    pub fn set_stick_assignment(&mut self, gamepad: Gamepad, stick: StickSide) -> bool {
+        let Some(tracker) = self.tracker_mut(gamepad) else { return false };
+        tracker.set_stick_assignment(stick)
     }
-}
 
-fn update_joycon_data(mut joycons: ResMut<Joycons>, mut events: EventWriter<GamepadEventRaw>) {
-    for (_, wrapper) in &mut joycons.trackers {
-        // TODO: identify and remove disconnected joycons
-        let Some(report) = wrapper.last_report.read() else { continue };
+    /// Sets how a single joycon is held, which changes how its buttons are
+    /// mapped. This also resets its stick rotation and assignment to suit the
+    /// layout, which can be changed again afterwards. If the gyro drives the
+    /// left stick, the joycon's stick keeps its current assignment.
+    ///
+    /// Returns false if the gamepad isn't a known single joycon.
+    pub fn set_layout(&mut self, gamepad: Gamepad, layout: JoyconLayout) -> bool {
+        let Some(tracker) = self.tracker_mut(gamepad) else { return false };
+        if !tracker.info.is_single_joycon() {
+            return false;
+        }
 
-        match wrapper.info.which {
-            WhichController::LeftJoyCon => {
-                // Rotate data by 90 degrees.
-                send_axis_event(
-                    &mut events,
-                    wrapper.gamepad,
-                    GamepadAxisType::LeftStickX,
-                    -report.left_stick.y,
-                    GamepadAxisType::LeftStickY,
-                    report.left_stick.x,
-                );
-            }
+        tracker.set_layout(layout);
+        true
+    }
 
-            WhichController::RightJoyCon => {
-                // Treat the single stick as the left stick even though it's the
-                // right joycon. Also, rotate it by 90 degrees.
-                send_axis_event(
-                    &mut events,
-                    wrapper.gamepad,
-                    GamepadAxisType::LeftStickX,
-                    report.right_stick.y,
-                    GamepadAxisType::LeftStickY,
-                    -report.right_stick.x,
-                );
-            }
+    pub fn get_layout(&self, gamepad: Gamepad) -> Option<JoyconLayout> {
+        Some(self.tracker(gamepad)?.layout)
+    }
 
-            WhichController::ProController => {
-                send_axis_event(
-                    &mut events,
-                    wrapper.gamepad,
-                    GamepadAxisType::LeftStickX,
-                    report.left_stick.x,
-                    GamepadAxisType::LeftStickY,
-                    report.left_stick.y,
-                );
-                send_axis_event(
-                    &mut events,
-                    wrapper.gamepad,
-                    GamepadAxisType::RightStickX,
-                    report.right_stick.x,
-                    GamepadAxisType::RightStickY,
-                    report.right_stick.y,
-                );
-            }
+    /// Makes a single joycon switch layouts by itself, as with `set_layout`,
+    /// depending on whether it's held sideways or upright. How it's held is
+    /// detected from gravity, so this requires the IMU to be enabled with
+    /// `enable_imu`. The layout only changes once the joycon is clearly held
+    /// the other way, so that it doesn't keep switching when held at an angle.
+    ///
+    /// Returns false if the gamepad isn't a known single joycon.
+    pub fn set_auto_layout(&mut self, gamepad: Gamepad, enabled: bool) -> bool {
+        let Some(tracker) = self.tracker_mut(gamepad) else { return false };
+        if !tracker.info.is_single_joycon() {
+            return false;
         }
+
+        tracker.auto_layout = enabled;
+        true
     }
-}
 
-fn send_axis_event(
-    events: &mut EventWriter<GamepadEventRaw>,
-    gamepad: Gamepad,
-    x_axis: GamepadAxisType,
-    x: f64,
-    y_axis: GamepadAxisType,
-    y: f64,
-) {
-    events.send(GamepadEventRaw::new(
-        gamepad,
-        GamepadEventType::AxisChanged(x_axis, x as f32),
-    ));
-    events.send(GamepadEventRaw::new(
-        gamepad,
-        GamepadEventType::AxisChanged(y_axis, y as f32),
-    ));
-}
-
-fn joycon_polling_thread(
-    mut joycon_device: JoyconDevice,
-    product_string: String,
-    serial_number: String,
-    last_report: Arc<Pinboard<JoyconReport>>,
-) {
-    loop {
-        let report = match joycon_device.tick() {
-            Ok(x) => x,
-            Err(e) => {
-                error!(
-                    "Error updating '{}' ({}): {}",
-                    product_string, serial_number, e
-                );
-                last_report.clear();
-                break;
+    /// Sets how the controller's D-pad is reported, overriding
+    /// `JoyconSettings::dpad_mode`. `None` goes back to using the setting.
+    ///
+    /// Returns false if the gamepad isn't a known joycon.
+    pub fn set_dpad_mode(&mut self, gamepad: Gamepad, mode: Option<DpadMode>) -> bool {
+        let Some(tracker) = self.tracker_mut(gamepad) else { return false };
+        tracker.dpad_mode = mode;
+        true
+    }
+
+    /// Returns the controller's D-pad mode override, if any.
+    pub fn get_dpad_mode(&self, gamepad: Gamepad) -> Option<DpadMode> {
+        self.tracker(gamepad)?.dpad_mode
+    }
+
+    /// Replaces the button mapping of every controller of the given type,
+    /// whatever its layout. Buttons that aren't mapped anymore are released.
+    /// Start from [`ButtonMap::default_for`] to only change some buttons.
+    pub fn set_button_map(&mut self, which: WhichController, map: ButtonMap) {
+        self.clear_button_map(which);
+        self.button_maps.push((which, map));
+    }
+
+    /// Goes back to the built-in button mapping for controllers of the given
+    /// type.
+    pub fn clear_button_map(&mut self, which: WhichController) {
+        self.button_maps.retain(|(mapped, _)| *mapped != which);
+    }
+
+    /// Returns the button mapping set for controllers of the given type, if
+    /// the built-in one was replaced.
+    pub fn get_button_map(&self, which: WhichController) -> Option<&ButtonMap> {
+        self.button_maps
+            .iter()
+            .find(|(mapped, _)| *mapped == which)
+            .map(|(_, map)| map)
+    }
+
+    pub fn get_stick_assignment(&self, gamepad: Gamepad) -> Option<StickSide> {
+        Some(self.tracker(gamepad)?.stick_assignment)
+    }
+
+    /// Blinks the controller's player lights and rumbles it briefly, so that a
+    /// player can tell which controller this gamepad is. If rumble couldn't be
+    /// enabled for the controller, only the lights blink.
+    ///
+    /// Returns false if the gamepad isn't a known joycon, or if it's not being
+    /// polled anymore.
+    pub fn identify(&self, gamepad: Gamepad) -> bool {
+        let Some(tracker) = self.tracker(gamepad) else { return false };
+        if tracker.commands.send(DeviceCommand::Identify).is_err() {
+            warn!("Can't identify {:?}, it's not being polled", gamepad);
+            return false;
+        }
+        true
+    }
+
+    /// Returns roughly how old the input the game is acting on is: the time
+    /// since the polling thread received the last report that was processed.
+    /// This is an estimate, which doesn't include the time the report took to
+    /// reach the computer. Returns `None` if the gamepad isn't a known joycon
+    /// or hasn't been updated yet.
+    pub fn estimated_latency(&self, gamepad: Gamepad) -> Option<Duration> {
+        Some(self.tracker(gamepad)?.report_received_at?.elapsed())
+    }
+
+    /// Sends an arbitrary subcommand to a joycon without waiting for it. The
+    /// raw reply arrives on the returned receiver once the joycon answers,
+    /// which can be checked with `try_recv` every frame. In immediate mode,
+    /// the subcommand is sent during the next update.
+    ///
+    /// This is an unsupported escape hatch for experimenting with the
+    /// protocol. Subcommands can change the joycon's state in ways this crate
+    /// doesn't know about, and break it until it's reconnected.
+    #[cfg(feature = "raw-subcommands")]
+    pub fn send_raw_subcommand(
+        &self,
+        gamepad: Gamepad,
+        id: u8,
+        data: &[u8],
+    ) -> Result<Receiver<Result<Vec<u8>>>> {
+        let tracker = self
+            .tracker(gamepad)
+            .with_context(|| format!("{:?} isn't a known joycon", gamepad))?;
+
+        let (reply, receiver) = channel();
+        tracker
+            .commands
+            .send(DeviceCommand::RawSubcommand {
+                id,
+                data: data.to_vec(),
+                reply,
+            })
+            .ok()
+            .with_context(|| format!("{:?} isn't being polled", gamepad))?;
+
+        Ok(receiver)
+    }
+
+    /// Rumbles the controller following an envelope. Playing an envelope while
+    /// another one is playing restarts from the new one's attack. Frequencies
+    /// and amplitudes out of range are handled according to
+    /// `JoyconsPlugin::rumble_range_policy`.
+    ///
+    /// Returns false if the gamepad isn't a known joycon, if it's not being
+    /// polled, or if the envelope was rejected for being out of range.
+    pub fn play_rumble_envelope(&self, gamepad: Gamepad, envelope: RumbleEnvelope) -> bool {
+        let Some(tracker) = self.tracker(gamepad) else { return false };
+        let envelope = if envelope.is_in_range() {
+            envelope
+        } else {
+            match self.rumble_range_policy {
+                RumbleRangePolicy::Clamp => envelope.clamped(),
+                RumbleRangePolicy::Reject => {
+                    warn!(
+                        "Not rumbling {:?}, {} Hz at amplitude {} is out of range",
+                        gamepad, envelope.frequency, envelope.sustain_level
+                    );
+                    return false;
+                }
             }
         };
+        if tracker
+            .commands
+            .send(DeviceCommand::PlayRumbleEnvelope(envelope))
+            .is_err()
+        {
+            warn!("Can't rumble {:?}, it's not being polled", gamepad);
+            return false;
+        }
+        *tracker.rumble.lock().unwrap() = Some((envelope, Instant::now()));
+        true
+    }
+
+    /// Returns the rumble envelope that's playing, as last set with
+    /// [`Self::play_rumble_envelope`], or `None` once it's over. The
+    /// controller can't be asked, so this doesn't include the rumble of
+    /// [`Self::identify`].
+    pub fn get_rumble(&self, gamepad: Gamepad) -> Option<RumbleState> {
+        let (envelope, started) = (*self.tracker(gamepad)?.rumble.lock().unwrap())?;
+        let amplitude = envelope.amplitude_at(started.elapsed())?;
+        Some(RumbleState {
+            envelope,
+            started,
+            amplitude,
+        })
+    }
+
+    /// Turns the controller's accelerometer and gyro on or off. They're off by
+    /// default, because they drain the battery faster, unless
+    /// `JoyconsPlugin::default_imu` is set. Motion data shows up in
+    /// snapshots a few reports after enabling them. It's kept if the joycon
+    /// reconnects.
+    ///
+    /// Returns false if the gamepad isn't a known joycon.
+    pub fn enable_imu(&mut self, gamepad: Gamepad, enabled: bool) -> bool {
+        let Some(tracker) = self.tracker_mut(gamepad) else { return false };
+        tracker.set_imu_enabled(enabled);
+        true
+    }
+
+    /// Assigns a player number from 1 to 8 to the gamepad, and lights up the
+    /// player lights in the pattern given by [`player_light_pattern`]. It's
+    /// kept if the joycon reconnects.
+    ///
+    /// Returns false if the gamepad isn't a known joycon, or if the number is
+    /// out of range, which also logs a warning.
+    pub fn set_player_number(&mut self, gamepad: Gamepad, player_number: u8) -> bool {
+        if !(1..=MAX_PLAYER_NUMBER).contains(&player_number) {
+            warn!(
+                "Player number {} is out of range, only players 1 to {} can be shown",
+                player_number, MAX_PLAYER_NUMBER
+            );
+            return false;
+        }
+        let Some(tracker) = self.tracker_mut(gamepad) else { return false };
+        tracker.set_player_number(player_number);
+        true
+    }
+
+    pub fn get_player_number(&self, gamepad: Gamepad) -> Option<u8> {
+        self.tracker(gamepad)?.player_number
+    }
+
+    /// Returns which of the 4 player lights were last set to be lit, from left
+    /// to right, e.g. to show them in a settings screen. The controller can't
+    /// be asked, so this doesn't include the blinking of
+    /// [`Self::identify`].
+    pub fn get_player_lights(&self, gamepad: Gamepad) -> Option<[bool; 4]> {
+        let tracker = self.tracker(gamepad)?;
+        Some(
+            tracker
+                .player_number
+                .and_then(player_light_pattern)
+                .unwrap_or_default(),
+        )
+    }
+
+    /// Reserves `count` player slots, e.g. for a "press to join" menu. Each
+    /// joycon that connects afterwards takes the first free slot, gets the
+    /// matching player number (for the first 8 slots) and sends a
+    /// [`JoyconSlotAssigned`] event. A slot is freed when its joycon
+    /// disconnects.
+    ///
+    /// Joycons that are already connected aren't assigned a slot. Reserving
+    /// fewer slots than before frees the ones at the end.
+    pub fn reserve_slots(&mut self, count: usize) {
+        self.slots.resize(count, None);
+    }
+
+    /// Returns the slot that the gamepad took, see [`Joycons::reserve_slots`].
+    pub fn slot_for_gamepad(&self, gamepad: Gamepad) -> Option<usize> {
+        self.slots.iter().position(|slot| *slot == Some(gamepad))
+    }
+
+    /// Returns the gamepad in a slot, if one took it.
+    pub fn gamepad_in_slot(&self, slot: usize) -> Option<Gamepad> {
+        self.slots.get(slot).copied().flatten()
+    }
+
+    /// Puts the gamepad in the first free slot, returning it.
+    fn take_free_slot(&mut self, gamepad: Gamepad) -> Option<usize> {
+        let slot = self.slots.iter().position(Option::is_none)?;
+        self.slots[slot] = Some(gamepad);
+        Some(slot)
+    }
+
+    fn free_slot(&mut self, gamepad: Gamepad) {
+        for slot in &mut self.slots {
+            if *slot == Some(gamepad) {
+                *slot = None;
+            }
+        }
+    }
+
+    /// Returns the last value sent in an event for one of the gamepad's axes.
+    /// Unlike Bevy's `Axis<GamepadAxis>`, this only covers joycons, and isn't
+    /// affected by `GamepadSettings`.
+    pub fn get_axis_value(&self, gamepad: Gamepad, axis: GamepadAxisType) -> Option<f32> {
+        self.tracker(gamepad)?.axis_values.get(&axis).copied()
+    }
+
+    /// Returns whether the last event sent for one of the gamepad's buttons
+    /// was a press. Returns false if the gamepad isn't a known joycon.
+    pub fn is_button_pressed(&self, gamepad: Gamepad, button: GamepadButtonType) -> bool {
+        self.tracker(gamepad).map_or(false, |tracker| {
+            tracker.pressed_buttons.contains_key(&button)
+        })
+    }
+
+    /// Returns the gamepad's buttons that were pressed during the last update,
+    /// or `None` if the gamepad isn't a known joycon. A button that was pressed
+    /// and released between two updates isn't included, since its press was
+    /// never sent.
+    pub fn buttons_pressed_this_frame(
+        &self,
+        gamepad: Gamepad,
+    ) -> Option<&HashSet<GamepadButtonType>> {
+        Some(&self.tracker(gamepad)?.pressed_this_frame)
+    }
+
+    /// Returns the gamepad's buttons that were released during the last
+    /// update, or `None` if the gamepad isn't a known joycon.
+    pub fn buttons_released_this_frame(
+        &self,
+        gamepad: Gamepad,
+    ) -> Option<&HashSet<GamepadButtonType>> {
+        Some(&self.tracker(gamepad)?.released_this_frame)
+    }
+
+    /// Returns how long one of the gamepad's buttons has been continuously
+    /// held, or `None` if it isn't pressed or the gamepad isn't a known joycon.
+    pub fn button_held_duration(
+        &self,
+        gamepad: Gamepad,
+        button: GamepadButtonType,
+    ) -> Option<Duration> {
+        let pressed_since = self.tracker(gamepad)?.pressed_buttons.get(&button)?;
+        Some(pressed_since.elapsed())
+    }
+
+    /// Estimates how two controllers are tilted relative to each other, e.g.
+    /// the two hands holding a pair of single joycons. Returns `None` if either
+    /// gamepad isn't a known joycon, or has no motion data because its IMU
+    /// isn't enabled with `enable_imu`.
+    pub fn relative_pose(&self, first: Gamepad, second: Gamepad) -> Option<RelativePose> {
+        let gravity = |gamepad| {
+            let imu = self.tracker(gamepad)?.snapshot.as_ref()?.imu.as_ref()?;
+            Some(average_accel(imu))
+        };
+
+        RelativePose::new(gravity(first)?, gravity(second)?)
+    }
+
+    /// Returns the motion samples from the controller's last report, for
+    /// doing sensor fusion or other processing outside of this crate. Returns
+    /// `None` if the gamepad isn't a known joycon, or has no motion data
+    /// because its IMU isn't enabled with `enable_imu`.
+    pub fn imu_samples(&self, gamepad: Gamepad) -> Option<ImuSamples> {
+        let tracker = self.tracker(gamepad)?;
+        let imu = tracker.snapshot.as_ref()?.imu.as_ref()?;
+        Some(ImuSamples::new(imu, tracker.report_received_at?))
+    }
+
+    /// Returns how far a controller held like a steering wheel is turned, from
+    /// -1.0 (fully left) to 1.0 (fully right), based on its tilt around the
+    /// axis through its face. The center is where the controller was when its
+    /// motion data first arrived, until [`Self::recenter_steering`] is called.
+    /// Returns `None` if the gamepad isn't a known joycon, or has no motion
+    /// data because its IMU isn't enabled with `enable_imu`.
+    pub fn steering_angle(&self, gamepad: Gamepad) -> Option<f32> {
+        let tracker = self.tracker(gamepad)?;
+        let imu = tracker.snapshot.as_ref()?.imu.as_ref()?;
+        tracker.steering.value(average_accel(imu))
+    }
+
+    /// Makes the controller's current tilt the center for `steering_angle`.
+    ///
+    /// Returns false if the gamepad isn't a known joycon, or has no motion
+    /// data.
+    pub fn recenter_steering(&mut self, gamepad: Gamepad) -> bool {
+        let Some(tracker) = self.tracker_mut(gamepad) else { return false };
+        let Some(imu) = tracker.snapshot.as_ref().and_then(|snapshot| snapshot.imu) else {
+            return false;
+        };
+        tracker.steering.recenter(average_accel(&imu));
+        true
+    }
+
+    /// Sets how far, in radians, the controller has to be turned from the
+    /// center for `steering_angle` to reach -1.0 or 1.0. Defaults to a quarter
+    /// turn. It's kept if the joycon reconnects.
+    ///
+    /// Returns false if the gamepad isn't a known joycon, or `range` isn't
+    /// positive.
+    pub fn set_steering_range(&mut self, gamepad: Gamepad, range: f32) -> bool {
+        if range.is_nan() || range <= 0.0 {
+            return false;
+        }
+        let Some(tracker) = self.tracker_mut(gamepad) else { return false };
+        tracker.steering.range = range;
+        true
+    }
+
+    /// Returns the settings of every joycon that is or was connected, that are
+    /// worth keeping between sessions.
+    pub fn export_settings(&self) -> JoyconPersistedSettings {
+        let disconnected = self
+            .saved_settings
+            .iter()
+            .map(|(serial_number, settings)| (serial_number.clone(), settings.persisted()));
+        // Imported settings win over older ones, as they will when the joycon
+        // connects.
+        let imported = self
+            .imported_settings
+            .iter()
+            .map(|(serial_number, settings)| (serial_number.clone(), *settings));
+        let connected = self.trackers.iter().map(|(_, tracker)| {
+            (
+                tracker.info.serial_number.clone(),
+                tracker.runtime_settings().persisted(),
+            )
+        });
+
+        JoyconPersistedSettings {
+            controllers: disconnected.chain(imported).chain(connected).collect(),
+        }
+    }
+
+    /// Applies settings from `export_settings`, e.g. from a previous session.
+    /// Settings of joycons that aren't connected are applied when they
+    /// connect. Only the settings in [`PersistedControllerSettings`] are
+    /// changed, the others keep their defaults or their values from earlier
+    /// in the session.
+    pub fn import_settings(&mut self, settings: &JoyconPersistedSettings) {
+        for (serial_number, settings) in &settings.controllers {
+            let mut settings = *settings;
+            for calibration in &mut settings.custom_calibration {
+                if calibration.map_or(false, |calibration| !calibration.is_valid()) {
+                    warn!(
+                        "Ignoring invalid custom stick calibration for {}",
+                        serial_number
+                    );
+                    *calibration = None;
+                }
+            }
+
+            let tracker = self
+                .trackers
+                .iter_mut()
+                .map(|(_, tracker)| tracker)
+                .find(|tracker| tracker.info.serial_number == *serial_number);
+
+            match tracker {
+                Some(tracker) => tracker.apply_persisted(&settings),
+                None => {
+                    self.imported_settings
+                        .insert(serial_number.clone(), settings);
+                }
+            }
+        }
+    }
+
+    /// Returns the error that stopped the gamepad's joycon from being polled,
+    /// which usually disconnects it. Kept after the disconnection, until the
+    /// same joycon connects again.
+    pub fn last_poll_error(&self, gamepad: Gamepad) -> Option<String> {
+        match self.tracker(gamepad) {
+            Some(tracker) => tracker.poll_error.lock().unwrap().clone(),
+            None => Some(self.poll_errors.get(&gamepad)?.1.clone()),
+        }
+    }
+
+    /// Returns whether the gamepad was released with [`Self::release`], so that
+    /// it's connected but not sending input events. It sends them again once
+    /// it's passed to [`Self::reacquire`]. This doesn't cover
+    /// `JoyconSettings::input_enabled`, which pauses every joycon.
+    ///
+    /// Returns `None` if the gamepad isn't a connected joycon.
+    pub fn is_released(&self, gamepad: Gamepad) -> Option<bool> {
+        Some(self.tracker(gamepad)?.released)
+    }
+
+    fn tracker(&self, gamepad: Gamepad) -> Option<&Tracker> {
+        self.trackers.get(gamepad)
+    }
+
+    fn tracker_mut(&mut self, gamepad: Gamepad) -> Option<&mut Tracker> {
+        self.trackers.get_mut(gamepad)
+    }
+
+    /// Removes a tracker along with its entries in the lookup maps. Dropping
+    /// the tracker also tells its polling thread to exit.
+    fn remove_tracker(&mut self, index: Index) -> Option<Tracker> {
+        let tracker = self.trackers.remove(index)?;
+        if let Some(error) = tracker.poll_error.lock().unwrap().take() {
+            self.poll_errors
+                .insert(tracker.gamepad, (tracker.info.serial_number.clone(), error));
+        }
+        self.saved_settings.insert(
+            tracker.info.serial_number.clone(),
+            tracker.runtime_settings(),
+        );
+        self.joycons_by_serial_number
+            .remove(&tracker.info.serial_number);
+        Some(tracker)
+    }
+}
+
+/// Decides when to scan for connection changes, backing off while nothing
+/// changes.
+#[derive(Resource)]
+struct DetectionSchedule {
+    min_interval: Duration,
+    max_interval: Duration,
+    interval: Duration,
+    next_detection: Instant,
+}
+
+impl DetectionSchedule {
+    /// Backing off starts from here if the minimum interval is shorter.
+    const BACKOFF_START: Duration = Duration::from_millis(50);
+
+    fn new(min_interval: Duration, max_interval: Duration) -> Self {
+        Self {
+            min_interval,
+            max_interval: max_interval.max(min_interval),
+            interval: min_interval,
+            next_detection: Instant::now(),
+        }
+    }
+
+    fn detected(&mut self, now: Instant, changed: bool) {
+        self.interval = if changed {
+            self.min_interval
+        } else {
+            (self.interval * 2)
+                .max(self.min_interval)
+                .max(Self::BACKOFF_START)
+                .min(self.max_interval)
+        };
+        self.next_detection = now + self.interval;
+    }
+
+    /// Makes the next detection happen as soon as possible.
+    fn detect_now(&mut self) {
+        self.interval = self.min_interval;
+        self.next_detection = Instant::now();
+    }
+}
+
+/// Resets joycons whose polling thread stopped hearing from the device without
+/// hitting an error, e.g. because it's stuck on an unresponsive device. Their
+/// thread is shut down, which makes the next detection pass disconnect them and
+/// reopen the device.
+fn watch_polling_threads(
+    joycons: Res<Joycons>,
+    settings: Res<JoyconSettings>,
+    mut schedule: ResMut<DetectionSchedule>,
+) {
+    let Some(report_timeout) = settings.report_timeout else { return };
+    // Devices are read by `update_joycon_data`, so a slow frame would look
+    // like every device is stuck.
+    if joycons.immediate_mode {
+        return;
+    }
+    let now = Instant::now();
+
+    for (_, tracker) in joycons.trackers.iter() {
+        if tracker.shutdown.load(Ordering::SeqCst) {
+            continue;
+        }
+        let heartbeat = *tracker.heartbeat.lock().unwrap();
+        let age = now.saturating_duration_since(heartbeat);
+        if age < report_timeout {
+            continue;
+        }
+
+        warn!(
+            "'{}' ({}) hasn't sent a report in {:?}, reopening it",
+            tracker.info.product_string, tracker.info.serial_number, age
+        );
+        // If the thread is stuck, it exits once it's unstuck and sees that it
+        // was shut down.
+        tracker.shutdown.store(true, Ordering::SeqCst);
+        schedule.detect_now();
+    }
+}
+
+fn detect_connection_changes(
+    mut hidapi: NonSendMut<HidApi>,
+    mut joycons: ResMut<Joycons>,
+    mut schedule: ResMut<DetectionSchedule>,
+    hooks: Res<JoyconHooks>,
+    gamepads: Option<Res<Gamepads>>,
+    mut event_writer: EventWriter<RawGamepadEvent>,
+    mut calibration_warnings: EventWriter<JoyconCalibrationWarning>,
+    mut rejections: EventWriter<JoyconRejected>,
+    mut slot_assignments: EventWriter<JoyconSlotAssigned>,
+) {
+    let now = Instant::now();
+    if now < schedule.next_detection {
+        return;
+    }
+
+    let mut events = GamepadEvents::new(&mut event_writer, joycons.log_buttons);
+    let changed = match detect_connection_changes_inner(
+        &mut hidapi,
+        &mut joycons,
+        &hooks,
+        gamepads.as_deref(),
+        &mut events,
+        &mut calibration_warnings,
+        &mut rejections,
+        &mut slot_assignments,
+    ) {
+        Ok(changed) => changed,
+        Err(e) => {
+            error!("Error detecting joycon connections/disconnections: {}", e);
+            false
+        }
+    };
+
+    schedule.detected(now, changed);
+}
+
+/// Returns whether any joycon connected or disconnected.
+fn detect_connection_changes_inner(
+    hidapi: &mut HidApi,
+    joycons: &mut Joycons,
+    hooks: &JoyconHooks,
+    gamepads: Option<&Gamepads>,
+    events: &mut GamepadEvents,
+    calibration_warnings: &mut EventWriter<JoyconCalibrationWarning>,
+    rejections: &mut EventWriter<JoyconRejected>,
+    slot_assignments: &mut EventWriter<JoyconSlotAssigned>,
+) -> Result<bool> {
+    hidapi
+        .refresh_devices()
+        .context("Refreshing hidapi device list")?;
+    joycons.last_refresh = Some(Instant::now());
+
+    let mut present_serial_numbers = HashSet::new();
+    let mut changed = false;
+
+    for device_info in joycon_interfaces(hidapi) {
+        let Some(serial_num) = device_info.serial_number() else {
+            error!("Bad joycon serial number");
+            continue;
+        };
+        // Platforms and hidapi versions don't agree on the formatting, so make
+        // sure the same joycon always has the same serial number.
+        let serial_num = normalize_serial_number(serial_num);
+        let _span = info_span!("joycon_detection", serial_number = %serial_num).entered();
+
+        present_serial_numbers.insert(serial_num.clone());
+
+        if joycons.joycons_by_serial_number.contains_key(&serial_num)
+            || joycons.vetoed_serial_numbers.contains(&serial_num)
+        {
+            continue;
+        }
+
+        if joycons
+            .max_controllers
+            .map_or(false, |max| joycons.trackers.len() >= max)
+        {
+            if joycons.rejected_serial_numbers.insert(serial_num.clone()) {
+                info!("Not opening {}, too many joycons are connected", serial_num);
+                rejections.send(JoyconRejected {
+                    serial_number: serial_num,
+                });
+            }
+            continue;
+        }
+        joycons.rejected_serial_numbers.remove(&serial_num);
+
+        if let Some(limit) = joycons.reconnect_limit {
+            let now = Instant::now();
+            let times = joycons
+                .connection_times
+                .entry(serial_num.clone())
+                .or_default();
+            times.retain(|&time| now.duration_since(time) < limit.window);
+            if times.len() >= limit.max_connections {
+                if joycons.unstable_serial_numbers.insert(serial_num.clone()) {
+                    warn!(
+                        "{} is connecting and disconnecting too often, ignoring it for now",
+                        serial_num
+                    );
+                }
+                continue;
+            }
+            joycons.unstable_serial_numbers.remove(&serial_num);
+        }
+
+        let product_string = product_string(device_info);
+
+        let options = match &hooks.connection {
+            Some(policy) => {
+                let request = ConnectionRequest {
+                    device_info,
+                    serial_number: &serial_num,
+                    product_string: &product_string,
+                };
+                match policy(&request) {
+                    ConnectionDecision::Accept(options) => options,
+                    ConnectionDecision::Reject => {
+                        info!(
+                            "Not opening {}, rejected by the connection policy",
+                            serial_num
+                        );
+                        joycons.vetoed_serial_numbers.insert(serial_num);
+                        continue;
+                    }
+                }
+            }
+            None => ConnectionOptions::default(),
+        };
+        let product_string = options.name.unwrap_or(product_string);
+
+        changed = true;
+
+        let requested_gamepad = options.gamepad.filter(|&gamepad| {
+            if !REQUESTED_GAMEPAD_IDS.contains(&gamepad.id) {
+                warn!(
+                    "{:?} requested for {} is outside of REQUESTED_GAMEPAD_IDS, using a new gamepad",
+                    gamepad, serial_num
+                );
+                return false;
+            }
+            // Bevy's gamepads include ones that don't belong to this plugin.
+            let in_use = joycons.trackers.get(gamepad).is_some()
+                || joycons.held_gamepads.values().any(|&held| held == gamepad)
+                || gamepads.map_or(false, |gamepads| gamepads.contains(gamepad));
+            if in_use {
+                warn!(
+                    "{:?} requested for {} is already in use, using a new gamepad",
+                    gamepad, serial_num
+                );
+            }
+            !in_use
+        });
+        let held_gamepad = joycons.held_gamepads.remove(&serial_num);
+        let gamepad = held_gamepad
+            .or(requested_gamepad)
+            .unwrap_or_else(|| Gamepad {
+                id: joycons.next_gamepad_id.fetch_add(1, Ordering::SeqCst),
+            });
+        let initial_tick = !joycons.async_connect;
+        let index = match Tracker::new(
+            hidapi,
+            device_info,
+            serial_num.clone(),
+            gamepad,
+            initial_tick,
+            joycons.default_imu,
+        ) {
+            Ok((joycon_device, device_control, mut tracker)) => {
+                tracker.info.product_string = product_string.clone();
+                if let Some(settings) = joycons.saved_settings.remove(&serial_num) {
+                    tracker.apply_runtime_settings(settings);
+                }
+                if let Some(settings) = joycons.imported_settings.remove(&serial_num) {
+                    tracker.apply_persisted(&settings);
+                }
+
+                info!("'{}' ({}) connected", product_string, serial_num);
+                if joycons.reconnect_limit.is_some() {
+                    joycons
+                        .connection_times
+                        .entry(serial_num.clone())
+                        .or_default()
+                        .push(Instant::now());
+                }
+                joycons
+                    .poll_errors
+                    .retain(|_, (serial_number, _)| *serial_number != serial_num);
+
+                // Otherwise, this is done when the first report arrives.
+                if held_gamepad.is_some() {
+                    // Its gamepad never disconnected.
+                    tracker.announced = true;
+                } else if tracker.last_report.read().is_some() {
+                    events.connected(gamepad, product_string.clone());
+                    tracker.announced = true;
+                }
+
+                // A held gamepad kept its slot.
+                if held_gamepad.is_none() {
+                    if let Some(slot) = joycons.take_free_slot(gamepad) {
+                        if let Ok(player_number) = u8::try_from(slot + 1) {
+                            if player_number <= MAX_PLAYER_NUMBER {
+                                tracker.set_player_number(player_number);
+                            }
+                        }
+                        slot_assignments.send(JoyconSlotAssigned { gamepad, slot });
+                    }
+                }
+
+                if !tracker.calibration_valid {
+                    warn!(
+                        "'{}' ({}) has invalid stick calibration, using defaults",
+                        product_string, serial_num
+                    );
+                    // Otherwise, it's sent after the connection event, in
+                    // `update_joycon_data`.
+                    if tracker.announced {
+                        calibration_warnings.send(JoyconCalibrationWarning { gamepad });
+                    }
+                }
+
+                // This is run as soon as the device is first polled.
+                if joycons.identify_on_connect {
+                    let _ = tracker.commands.send(DeviceCommand::Identify);
+                }
+
+                let poller = DevicePoller {
+                    device: joycon_device,
+                    control: device_control,
+                    product_string: tracker.info.product_string.clone(),
+                    serial_number: tracker.info.serial_number.clone(),
+                    last_report: tracker.last_report.clone(),
+                    heartbeat: tracker.heartbeat.clone(),
+                    poll_error: tracker.poll_error.clone(),
+                    warned_bad_format: false,
+                    last_raw_report: Vec::new(),
+                    stick_stream: tracker.stick_stream.clone(),
+                };
+
+                if joycons.immediate_mode {
+                    tracker.poller = Some(Mutex::new(poller));
+                } else {
+                    // This needs a dedicated thread, otherwise we get (more?)
+                    // latency.
+                    spawn({
+                        let product_string = tracker.info.product_string.clone();
+                        let serial_number = tracker.info.serial_number.clone();
+                        let shutdown = tracker.shutdown.clone();
+                        let polling_threads = joycons.polling_threads.clone();
+                        polling_threads.fetch_add(1, Ordering::SeqCst);
+                        let priority = joycons.polling_thread_priority;
+
+                        move || {
+                            if let Some(priority) = priority {
+                                if let Err(e) = set_current_thread_priority(priority) {
+                                    warn!(
+                                        "Couldn't set polling thread priority for '{}' ({}): {:?}",
+                                        product_string, serial_number, e
+                                    );
+                                }
+                            }
+
+                            joycon_polling_thread(poller, shutdown);
+                            polling_threads.fetch_sub(1, Ordering::SeqCst);
+                        }
+                    });
+                }
+
+                let index = joycons.trackers.insert(gamepad, tracker);
+
+                Ok(index)
+            }
+
+            Err(e) => {
+                error!("Error opening '{}' ({}): {}", product_string, serial_num, e);
+                if let Some(gamepad) = held_gamepad {
+                    joycons.held_gamepads.insert(serial_num.clone(), gamepad);
+                }
+                // Remember that we had an error, so that we don't retry every
+                // frame.
+                Err(())
+            }
+        };
+
+        joycons.joycons_by_serial_number.insert(serial_num, index);
+    }
+
+    if hold_lost_joycons(joycons, events, &present_serial_numbers) {
+        changed = true;
+    }
+
+    // Joycons whose device is gone, whose polling thread hit an error, or that
+    // were found to be stuck, are disconnected. If the device is still there,
+    // it'll be reopened on the next pass.
+    let disconnected = joycons
+        .trackers
+        .iter()
+        .filter(|(_, tracker)| {
+            !present_serial_numbers.contains(&tracker.info.serial_number)
+                || (tracker.last_report.read().is_none()
+                    && (tracker.announced || tracker.poll_error.lock().unwrap().is_some()))
+                || tracker.shutdown.load(Ordering::SeqCst)
+        })
+        .map(|(index, _)| index)
+        .collect::<Vec<_>>();
+
+    for index in disconnected {
+        let Some(tracker) = joycons.remove_tracker(index) else { continue };
+        changed = true;
+
+        info!(
+            "'{}' ({}) disconnected",
+            tracker.info.product_string, tracker.info.serial_number
+        );
+
+        if tracker.announced {
+            events.disconnected(tracker.gamepad);
+        }
+        joycons.free_slot(tracker.gamepad);
+    }
+
+    // Forget about errors opening joycons that aren't there anymore, so that
+    // they're retried if they come back.
+    joycons
+        .joycons_by_serial_number
+        .retain(|serial_number, index| {
+            index.is_ok() || present_serial_numbers.contains(serial_number)
+        });
+    joycons
+        .rejected_serial_numbers
+        .retain(|serial_number| present_serial_numbers.contains(serial_number));
+    joycons
+        .vetoed_serial_numbers
+        .retain(|serial_number| present_serial_numbers.contains(serial_number));
+    joycons
+        .unstable_serial_numbers
+        .retain(|serial_number| present_serial_numbers.contains(serial_number));
+
+    Ok(changed)
+}
+
+/// Keeps the gamepads of joycons that all disappeared at once, as described
+/// for `JoyconsPlugin::hid_loss_grace`, and disconnects them once it's over.
+/// Returns true while waiting for them, so that detection keeps running often.
+fn hold_lost_joycons(
+    joycons: &mut Joycons,
+    events: &mut GamepadEvents,
+    present_serial_numbers: &HashSet<String>,
+) -> bool {
+    let Some(grace) = joycons.hid_loss_grace else { return false };
+    let now = Instant::now();
+
+    let all_lost = joycons.trackers.len() >= 2
+        && joycons
+            .trackers
+            .iter()
+            .all(|(_, tracker)| !present_serial_numbers.contains(&tracker.info.serial_number));
+    if all_lost {
+        warn!(
+            "All joycons disappeared at once, waiting {:?} for them to come back",
+            grace
+        );
+        let indices = joycons
+            .trackers
+            .iter()
+            .map(|(index, _)| index)
+            .collect::<Vec<_>>();
+        for index in indices {
+            let Some(mut tracker) = joycons.remove_tracker(index) else { continue };
+            if tracker.announced {
+                send_paused_state(events, &mut tracker);
+                joycons
+                    .held_gamepads
+                    .insert(tracker.info.serial_number.clone(), tracker.gamepad);
+            } else {
+                joycons.free_slot(tracker.gamepad);
+            }
+        }
+        joycons.hid_lost_at = Some(now);
+    }
+
+    let Some(lost_at) = joycons.hid_lost_at else { return false };
+    if joycons.held_gamepads.is_empty() {
+        joycons.hid_lost_at = None;
+        return false;
+    }
+    if now.duration_since(lost_at) < grace {
+        return true;
+    }
+
+    let held_gamepads = joycons.held_gamepads.drain().collect::<Vec<_>>();
+    for (serial_number, gamepad) in held_gamepads {
+        info!("{} didn't come back, disconnecting it", serial_number);
+        events.disconnected(gamepad);
+        joycons.free_slot(gamepad);
+    }
+    joycons.hid_lost_at = None;
+    true
+}
+
+fn normalize_serial_number(serial_number: &str) -> String {
+    serial_number.trim().to_uppercase()
+}
+
+/// Some controllers report an empty product string but are otherwise fine, so
+/// fall back to a name based on the product ID.
+fn product_string(device_info: &DeviceInfo) -> String {
+    match device_info.product_string() {
+        Some(product_string) if !product_string.trim().is_empty() => product_string.to_string(),
+        _ => fallback_product_string(device_info.product_id()),
+    }
+}
+
+fn fallback_product_string(product_id: u16) -> String {
+    match product_id {
+        0x2006 => "Joy-Con (L)".to_string(),
+        0x2007 => "Joy-Con (R)".to_string(),
+        0x2009 => "Pro Controller".to_string(),
+        0x200e => "Joy-Con Charging Grip".to_string(),
+        _ => format!("Nintendo controller ({:04x})", product_id),
+    }
+}
+
+fn is_joycon_device(device_info: &DeviceInfo) -> bool {
+    device_info.vendor_id() == NINTENDO_VENDOR_ID && HID_IDS.contains(&device_info.product_id())
+}
+
+/// Returns the joycons in the device list, with a single interface for each
+/// physical device, in the order they're listed. Some platforms list a device
+/// once for each of its HID interfaces or top-level collections, sometimes
+/// with a different or missing serial number, and opening the wrong one, or
+/// both, doesn't work.
+fn joycon_interfaces(hidapi: &HidApi) -> Vec<&DeviceInfo> {
+    let mut interfaces: Vec<(String, Option<String>, &DeviceInfo)> = Vec::new();
+    for device_info in hidapi.device_list() {
+        if !is_joycon_device(device_info) {
+            continue;
+        }
+
+        let path = device_info
+            .path()
+            .to_str()
+            .map(path_without_collection)
+            .unwrap_or_default();
+        let serial_number = device_info
+            .serial_number()
+            .map(normalize_serial_number)
+            .filter(|serial_number| !serial_number.is_empty());
+
+        let same_device = interfaces.iter_mut().find(|(other_path, other_serial, _)| {
+            (!path.is_empty() && *other_path == path)
+                || (serial_number.is_some() && *other_serial == serial_number)
+        });
+        match same_device {
+            Some((_, _, other)) => {
+                if interface_preference(device_info) > interface_preference(other) {
+                    *other = device_info;
+                }
+            }
+            None => interfaces.push((path, serial_number, device_info)),
+        }
+    }
+
+    interfaces
+        .into_iter()
+        .map(|(_, _, device_info)| device_info)
+        .collect()
+}
+
+/// Removes the top-level collection from a Windows device path, e.g.
+/// `&col01`, which is the only part that differs between the collections of
+/// the same device.
+fn path_without_collection(path: &str) -> String {
+    let path = path.to_ascii_lowercase();
+    match path.find("&col") {
+        Some(start) => {
+            let rest = path.get(start + "&col".len() + 2..).unwrap_or_default();
+            format!("{}{}", &path[..start], rest)
+        }
+        None => path,
+    }
+}
+
+/// Higher for interfaces that are more likely to be the one that sends input:
+/// the generic desktop joystick or gamepad collection, then the lowest
+/// interface number.
+fn interface_preference(device_info: &DeviceInfo) -> (bool, Reverse<i32>) {
+    let is_input = device_info.usage_page() == GENERIC_DESKTOP_USAGE_PAGE
+        && [JOYSTICK_USAGE, GAMEPAD_USAGE].contains(&device_info.usage());
+    (is_input, Reverse(device_info.interface_number()))
+}
+
+pub struct JoyconInfo {
+    pub product_string: String,
+    pub serial_number: String,
+    pub which: WhichController,
+    /// All zeroes if `color_available` is false.
+    pub color: ControllerColor,
+    /// False if the controller's colors couldn't be read. The controller is
+    /// still usable, this is only cosmetic.
+    pub color_available: bool,
+    pub use_spi_colors: UseSPIColors,
+    /// The colors from `color` that the controller says should be used,
+    /// according to `use_spi_colors`. All `None` if `color_available` is
+    /// false.
+    pub colors: JoyconColors,
+    pub capabilities: JoyconCapabilities,
+    /// Where the stick calibration comes from. Sticks using
+    /// [`CalibrationSource::Default`] may be off-center, so this is a good
+    /// thing to show in diagnostics.
+    pub calibration_source: CalibrationSource,
+    /// When the controller was opened. Joycons that connected around the same
+    /// time were likely attached together, see `Joycons::suggest_pairs`.
+    pub connected_at: Instant,
+    device_path: Option<String>,
+}
+
+impl JoyconInfo {
+    fn new(
+        device_info: &DeviceInfo,
+        serial_number: String,
+        joycon_device: &mut JoyconDevice,
+    ) -> Result<Self> {
+        let product_string = product_string(device_info);
+        let device_path = device_info.path().to_str().ok().map(str::to_string);
+
+        let joycon_dev_info = joycon_device
+            .get_dev_info()
+            .context("Getting joycon device info")?;
+        let which = joycon_dev_info
+            .which_controller
+            .try_into()
+            .context("Parsing joycon type")?;
+        let use_spi_colors = joycon_dev_info
+            .use_spi_colors
+            .try_into()
+            .context("Parsing joycon UseSPIColors data")?;
+        let capabilities = JoyconCapabilities::new(which, joycon_dev_info.firmware_version.0);
+        let calibration_source = calibration_source(joycon_device, which);
+
+        // Colors are only cosmetic, so don't fail to open the controller
+        // because of them.
+        let (color, color_available) = match joycon_device.read_spi() {
+            Ok(color) => (color, true),
+            Err(e) => {
+                warn!("Error reading controller color: {}", e);
+                (ControllerColor::default(), false)
+            }
+        };
+        let colors = if color_available {
+            JoyconColors::new(&color, use_spi_colors)
+        } else {
+            JoyconColors::new(&color, UseSPIColors::No)
+        };
+
+        Ok(Self {
+            product_string,
+            serial_number,
+            which,
+            use_spi_colors,
+            color,
+            color_available,
+            colors,
+            capabilities,
+            calibration_source,
+            connected_at: Instant::now(),
+            device_path,
+        })
+    }
+
+    /// Whether this is a left joycon.
+    pub fn is_left(&self) -> bool {
+        self.which == WhichController::LeftJoyCon
+    }
+
+    /// Whether this is a right joycon.
+    pub fn is_right(&self) -> bool {
+        self.which == WhichController::RightJoyCon
+    }
+
+    /// Whether this is a pro controller.
+    pub fn is_pro(&self) -> bool {
+        self.which == WhichController::ProController
+    }
+
+    /// Whether this is a single left or right joycon, with one stick.
+    pub fn is_single_joycon(&self) -> bool {
+        self.is_left() || self.is_right()
+    }
+
+    /// The platform-specific path of the controller's HID device, e.g. its
+    /// `/dev/hidraw*` node on Linux. `None` if the path isn't valid UTF-8.
+    pub fn device_path(&self) -> Option<&str> {
+        self.device_path.as_deref()
+    }
+}
+
+/// The full set of colors of a controller. Colors are `None` if the controller
+/// doesn't specify them, e.g. grip colors on controllers without grips.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct JoyconColors {
+    pub body: Option<SpiColor>,
+    pub buttons: Option<SpiColor>,
+    pub left_grip: Option<SpiColor>,
+    pub right_grip: Option<SpiColor>,
+}
+
+impl JoyconColors {
+    fn new(color: &ControllerColor, use_spi_colors: UseSPIColors) -> Self {
+        let (has_colors, has_grip_colors) = match use_spi_colors {
+            UseSPIColors::No => (false, false),
+            UseSPIColors::WithoutGrip => (true, false),
+            UseSPIColors::IncludingGrip => (true, true),
+        };
+
+        Self {
+            body: has_colors.then_some(color.body),
+            buttons: has_colors.then_some(color.buttons),
+            left_grip: has_grip_colors.then_some(color.left_grip),
+            right_grip: has_grip_colors.then_some(color.right_grip),
+        }
+    }
+}
+
+/// Per-controller settings changed through `Joycons`, which are kept when the
+/// controller disconnects and re-applied when it reconnects.
+struct RuntimeSettings {
+    rotation: StickRotation,
+    stick_zero: [[f64; 2]; 2],
+    custom_calibration: [Option<CustomCalibration>; 2],
+    gyro_stick: Option<(StickSide, f64)>,
+    stick_assignment: StickSide,
+    layout: JoyconLayout,
+    auto_layout: bool,
+    dpad_mode: Option<DpadMode>,
+    player_number: Option<u8>,
+    imu_enabled: bool,
+    steering: Steering,
+    turbo: HashMap<GamepadButtonType, Turbo>,
+    chords: Vec<Chord>,
+}
+
+impl RuntimeSettings {
+    fn persisted(&self) -> PersistedControllerSettings {
+        PersistedControllerSettings {
+            layout: self.layout,
+            rotation: self.rotation,
+            stick_assignment: self.stick_assignment,
+            stick_zero: self.stick_zero,
+            custom_calibration: self.custom_calibration,
+        }
+    }
+}
+
+struct Tracker {
+    info: JoyconInfo,
+    /// The last report, and when it was received. If the pinboard is empty,
+    /// then the joycon thread has hit an error, or it's still waiting for the
+    /// first report.
+    last_report: Arc<Pinboard<(JoyconReport, Instant)>>,
+    /// When the polling thread last heard from the device, including reports
+    /// that it didn't store. Checked by `watch_polling_threads`.
+    heartbeat: Arc<Mutex<Instant>>,
+    gamepad: Gamepad,
+    /// Whether the gamepad's connection event was sent. With
+    /// `JoyconsPlugin::async_connect`, that waits for the first report.
+    announced: bool,
+    /// Timer byte of the last report handled by `update_joycon_data`.
+    last_sequence: Option<u8>,
+    released: bool,
+    /// Whether the centered sticks and released buttons were sent after the
+    /// joycon was released or input was disabled.
+    sent_paused_state: bool,
+    /// If false, the joycon's stick calibration is garbage, so stick values
+    /// are computed from the raw report instead.
+    calibration_valid: bool,
+    /// Calibrations of the left and right physical sticks made with
+    /// `Joycons::start_calibration`, used instead of the controller's own.
+    custom_calibration: [Option<CustomCalibration>; 2],
+    /// The guided calibration in progress, or that's done.
+    calibration: Option<CalibrationAssistant>,
+    rotation: StickRotation,
+    /// Offsets subtracted from the left and right physical sticks, set by
+    /// `Joycons::zero_stick`.
+    stick_zero: [[f64; 2]; 2],
+    /// The stick driven by the gyro, and its sensitivity, set by
+    /// `Joycons::set_gyro_stick`.
+    gyro_stick: Option<(StickSide, f64)>,
+    /// Sticks that stopped being driven by this joycon, and still need to be
+    /// centered.
+    stale_sticks: Vec<StickSide>,
+    /// Which gamepad stick a single joycon's stick is reported as.
+    stick_assignment: StickSide,
+    layout: JoyconLayout,
+    /// Set by `Joycons::set_auto_layout`.
+    auto_layout: bool,
+    /// Set by `Joycons::set_dpad_mode`.
+    dpad_mode: Option<DpadMode>,
+    /// Set by `Joycons::set_player_number`.
+    player_number: Option<u8>,
+    /// Set by `Joycons::enable_imu`.
+    imu_enabled: bool,
+    steering: Steering,
+    /// When the polling thread received the last report handled by
+    /// `update_joycon_data`.
+    report_received_at: Option<Instant>,
+    /// Filtering state of the left and right physical sticks.
+    stick_filters: [StickFilters; 2],
+    /// Unprocessed values of the left and right physical sticks, from the last
+    /// report handled by `update_joycon_data`.
+    native_sticks: Option<[[f64; 2]; 2]>,
+    /// Resting drift of the left and right physical sticks.
+    drift: [DriftHistory; 2],
+    /// Raw 12-bit values of the left and right physical sticks, from the last
+    /// report handled by `update_joycon_data`.
+    raw_sticks: Option<[(u16, u16); 2]>,
+    /// State as of the last report handled by `update_joycon_data`.
+    snapshot: Option<JoyconSnapshot>,
+    /// The last value sent for each axis.
+    axis_values: HashMap<GamepadAxisType, f32>,
+    /// Buttons that were last reported as pressed, and when they were pressed.
+    pressed_buttons: HashMap<GamepadButtonType, Instant>,
+    /// Buttons that were pressed or released during the last update.
+    pressed_this_frame: HashSet<GamepadButtonType>,
+    released_this_frame: HashSet<GamepadButtonType>,
+    turbo: HashMap<GamepadButtonType, Turbo>,
+    /// Set by `Joycons::map_chord`.
+    chords: Vec<Chord>,
+    /// Buttons of suppressing chords that were held, which aren't reported
+    /// until they're released.
+    suppressed_buttons: HashSet<JoyconButton>,
+    /// When a button was last pressed or a stick last pushed, for idle
+    /// detection.
+    last_input: Instant,
+    /// Whether there was any input since the joycon connected. Until then,
+    /// `last_input` is the connection time.
+    had_input: bool,
+    /// Whether the left and right gamepad sticks were pushed far enough to
+    /// count as input during the last update.
+    active_sticks: [bool; 2],
+    /// Whether a `JoyconIdle` event was sent since the last input.
+    idle: bool,
+    /// Whether `JoyconsPlugin::idle_disconnect` was applied since the last
+    /// input.
+    idle_disconnected: bool,
+    /// Tells the polling thread to exit. Set when the tracker is dropped, or
+    /// by `watch_polling_threads` when the thread is stuck.
+    shutdown: Arc<AtomicBool>,
+    /// Set by the polling thread when it exits because of an error.
+    poll_error: Arc<Mutex<Option<String>>>,
+    /// Commands for the polling thread to send to the device.
+    commands: Sender<DeviceCommand>,
+    /// Reads the device in `update_joycon_data`, in immediate mode. Not used
+    /// from several threads, but needed for `Joycons` to be `Sync`.
+    poller: Option<Mutex<DevicePoller>>,
+    /// Set by `Joycons::set_stick_callback`, shared with the polling thread.
+    stick_stream: Arc<Mutex<Option<StickStream>>>,
+    /// The last envelope sent by `Joycons::play_rumble_envelope`, and when.
+    /// In a mutex because that only borrows `Joycons` immutably.
+    rumble: Mutex<Option<(RumbleEnvelope, Instant)>>,
+}
+
+impl Tracker {
+    fn new(
+        hidapi: &HidApi,
+        device_info: &DeviceInfo,
+        serial_number: String,
+        gamepad: Gamepad,
+        initial_tick: bool,
+        imu_enabled: bool,
+    ) -> Result<(JoyconDevice, DeviceControl, Self)> {
+        let device = device_info
+            .open_device(hidapi)
+            .context("Opening joycon hid device")?;
+        let mut joycon_device =
+            JoyconDevice::new(device, device_info.clone()).context("Initializing joycon")?;
+
+        joycon_device
+            .load_calibration()
+            .context("Loading calibration data")?;
+
+        let info = JoyconInfo::new(device_info, serial_number, &mut joycon_device)?;
+
+        let calibration_valid = info.calibration_source != CalibrationSource::Default;
+
+        let rumble_enabled = match enable_rumble(&mut joycon_device) {
+            Ok(()) => true,
+            Err(e) => {
+                warn!("Error enabling rumble: {}", e);
+                false
+            }
+        };
+
+        // Enabled before the first report, so that it already has motion data.
+        if imu_enabled {
+            joycon_device.enable_imu().context("Enabling IMU")?;
+        }
+
+        let last_report = if initial_tick {
+            let report = joycon_device.tick().context("Polling joycon first time")?;
+            Arc::new(Pinboard::new((report, Instant::now())))
+        } else {
+            Arc::new(Pinboard::new_empty())
+        };
+
+        let (commands, command_receiver) = channel();
+        let device_control = DeviceControl::new(command_receiver, rumble_enabled);
+        let tracker = Self::from_info(
+            info,
+            last_report,
+            gamepad,
+            calibration_valid,
+            imu_enabled,
+            commands,
+        );
+
+        Ok((joycon_device, device_control, tracker))
+    }
+
+    /// Sets up the tracker of an opened joycon, before anything is changed
+    /// through `Joycons`.
+    fn from_info(
+        info: JoyconInfo,
+        last_report: Arc<Pinboard<(JoyconReport, Instant)>>,
+        gamepad: Gamepad,
+        calibration_valid: bool,
+        imu_enabled: bool,
+        commands: Sender<DeviceCommand>,
+    ) -> Self {
+        let rotation = StickRotation::default_for(info.which);
+
+        Self {
+            info,
+            last_report,
+            heartbeat: Arc::new(Mutex::new(Instant::now())),
+            gamepad,
+            announced: false,
+            last_sequence: None,
+            released: false,
+            sent_paused_state: false,
+            calibration_valid,
+            custom_calibration: [None; 2],
+            calibration: None,
+            rotation,
+            stick_zero: Default::default(),
+            gyro_stick: None,
+            stale_sticks: Vec::new(),
+            stick_assignment: StickSide::Left,
+            layout: JoyconLayout::default(),
+            auto_layout: false,
+            dpad_mode: None,
+            player_number: None,
+            imu_enabled,
+            steering: Steering::default(),
+            report_received_at: None,
+            stick_filters: Default::default(),
+            native_sticks: None,
+            drift: Default::default(),
+            raw_sticks: None,
+            snapshot: None,
+            axis_values: HashMap::new(),
+            pressed_buttons: HashMap::new(),
+            pressed_this_frame: HashSet::new(),
+            released_this_frame: HashSet::new(),
+            turbo: HashMap::new(),
+            chords: Vec::new(),
+            suppressed_buttons: HashSet::new(),
+            last_input: Instant::now(),
+            had_input: false,
+            active_sticks: [false; 2],
+            idle: false,
+            idle_disconnected: false,
+            shutdown: Arc::new(AtomicBool::new(false)),
+            poll_error: Arc::new(Mutex::new(None)),
+            commands,
+            poller: None,
+            stick_stream: Arc::new(Mutex::new(None)),
+            rumble: Mutex::new(None),
+        }
+    }
+
+    /// Returns the `(x, y)` values of the left and right sticks.
+    fn stick_values(&self, report: &JoyconReport) -> [[f64; 2]; 2] {
+        stick_values(report, self.calibration_valid, &self.custom_calibration)
+    }
+
+    /// Moves the guided calibration along with the last report's raw stick
+    /// values, returning the stick and its new state if it changed.
+    fn update_calibration(&mut self, now: Instant) -> Option<(StickSide, CalibrationState)> {
+        let assistant = self.calibration.as_mut()?;
+        let raw = assistant.side.select(self.raw_sticks?);
+        let state = assistant.update(raw, now)?;
+        if let Some(calibration) = assistant.calibration() {
+            *assistant.side.select_mut(&mut self.custom_calibration) = Some(calibration);
+        }
+        Some((assistant.side, state))
+    }
+
+    /// Updates the stick callback's copy of the processing applied to the
+    /// sticks.
+    fn sync_stick_stream(&self, settings: &JoyconSettings) {
+        if let Some(stream) = &mut *self.stick_stream.lock().unwrap() {
+            stream.custom_calibration = self.custom_calibration;
+            stream.stick_zero = self.stick_zero;
+            stream.rotation = self.rotation;
+            stream.axis_convention = settings.axis_convention;
+        }
+    }
+
+    /// Which gamepad stick a physical stick is reported as.
+    fn output_stick(&self, side: StickSide) -> StickSide {
+        side.output_stick(self.info.which, self.stick_assignment)
+    }
+
+    fn set_player_number(&mut self, player_number: u8) {
+        self.player_number = Some(player_number);
+        if self
+            .commands
+            .send(DeviceCommand::SetPlayerNumber(player_number))
+            .is_err()
+        {
+            warn!(
+                "Can't set the player lights of {:?}, it's not being polled",
+                self.gamepad
+            );
+        }
+    }
+
+    /// Returns false if the stick is already driven by the gyro.
+    fn set_stick_assignment(&mut self, stick: StickSide) -> bool {
+        if self.gyro_stick.map_or(false, |(output, _)| output == stick) {
+            return false;
+        }
+
+        if self.stick_assignment != stick {
+            if self.info.is_single_joycon() {
+                self.stale_sticks.push(self.stick_assignment);
+            }
+            self.stick_assignment = stick;
+        }
+        true
+    }
+
+    fn set_layout(&mut self, layout: JoyconLayout) {
+        self.rotation = match layout {
+            JoyconLayout::Sideways => StickRotation::default_for(self.info.which),
+            JoyconLayout::UprightSolo => StickRotation::None,
+        };
+        // The physical stick is never assigned to the stick driven by the
+        // gyro, so keeping its assignment doesn't conflict.
+        if !self.set_stick_assignment(StickSide::Left) {
+            warn!(
+                "The left stick of {:?} is driven by the gyro, keeping its stick on the {:?} stick",
+                self.gamepad, self.stick_assignment
+            );
+        }
+        self.layout = layout;
+    }
+
+    fn set_imu_enabled(&mut self, enabled: bool) {
+        self.imu_enabled = enabled;
+        if self
+            .commands
+            .send(DeviceCommand::SetImuEnabled(enabled))
+            .is_err()
+        {
+            warn!(
+                "Can't set the IMU of {:?}, it's not being polled",
+                self.gamepad
+            );
+        }
+    }
+
+    fn runtime_settings(&self) -> RuntimeSettings {
+        RuntimeSettings {
+            rotation: self.rotation,
+            stick_zero: self.stick_zero,
+            custom_calibration: self.custom_calibration,
+            gyro_stick: self.gyro_stick,
+            stick_assignment: self.stick_assignment,
+            layout: self.layout,
+            auto_layout: self.auto_layout,
+            dpad_mode: self.dpad_mode,
+            player_number: self.player_number,
+            imu_enabled: self.imu_enabled,
+            steering: self.steering,
+            turbo: self.turbo.clone(),
+            chords: self.chords.clone(),
+        }
+    }
+
+    fn apply_runtime_settings(&mut self, settings: RuntimeSettings) {
+        self.rotation = settings.rotation;
+        self.stick_zero = settings.stick_zero;
+        self.custom_calibration = settings.custom_calibration;
+        self.gyro_stick = settings.gyro_stick;
+        self.stick_assignment = settings.stick_assignment;
+        self.layout = settings.layout;
+        self.auto_layout = settings.auto_layout;
+        self.dpad_mode = settings.dpad_mode;
+        if let Some(player_number) = settings.player_number {
+            self.set_player_number(player_number);
+        }
+        if settings.imu_enabled != self.imu_enabled {
+            self.set_imu_enabled(settings.imu_enabled);
+        }
+        // The controller is probably held differently now.
+        self.steering = settings.steering.uncentered();
+        self.turbo = settings.turbo;
+        self.chords = settings.chords;
+    }
+
+    /// Applies settings from `Joycons::import_settings`.
+    fn apply_persisted(&mut self, settings: &PersistedControllerSettings) {
+        // The layout resets the rotation and assignment, so it goes first.
+        if self.info.is_single_joycon() {
+            self.set_layout(settings.layout);
+        }
+        self.rotation = settings.rotation;
+        self.set_stick_assignment(settings.stick_assignment);
+        self.stick_zero = settings.stick_zero;
+        self.custom_calibration = settings.custom_calibration;
+    }
+
+    fn release(&mut self) {
+        if !self.released {
+            self.released = true;
+            self.pause();
+        }
+    }
+
+    /// Frees the joycon for the next player, after
+    /// `JoyconsPlugin::idle_disconnect`.
+    fn disconnect_idle(&mut self, action: IdleDisconnectAction) {
+        info!(
+            "'{}' ({}) had no input for too long, freeing it",
+            self.info.product_string, self.info.serial_number
+        );
+        self.release();
+        if action == IdleDisconnectAction::Disconnect
+            && self.commands.send(DeviceCommand::Disconnect).is_err()
+        {
+            warn!("Can't disconnect {:?}, it's not being polled", self.gamepad);
+        }
+    }
+
+    /// Called when the joycon stops sending input events, so that its neutral
+    /// state gets sent.
+    fn pause(&mut self) {
+        self.sent_paused_state = false;
+    }
+
+    /// Called when the joycon resumes sending input events, so that its current
+    /// state gets sent even if it didn't change, without any stale smoothing.
+    fn resume(&mut self) {
+        self.last_sequence = None;
+        self.stick_filters = Default::default();
+    }
+}
+
+impl Drop for Tracker {
+    fn drop(&mut self) {
+        self.shutdown.store(true, Ordering::SeqCst);
+    }
+}
+
+fn update_joycon_data(
+    mut joycons: ResMut<Joycons>,
+    settings: Res<JoyconSettings>,
+    hooks: Res<JoyconHooks>,
+    mut event_writer: EventWriter<RawGamepadEvent>,
+    mut axis_changes: EventWriter<JoyconAxisChanged>,
+    mut idle_events: EventWriter<JoyconIdle>,
+    mut wake_events: EventWriter<JoyconWake>,
+    mut calibration_warnings: EventWriter<JoyconCalibrationWarning>,
+    mut calibration_states: EventWriter<JoyconCalibrationStateChanged>,
+    mut idle_disconnects: EventWriter<JoyconIdleDisconnected>,
+) {
+    let joycons = &mut *joycons;
+    let mut events = GamepadEvents::new(&mut event_writer, joycons.log_buttons);
+    if joycons.axis_change_events {
+        events = events.with_axis_changes(&mut axis_changes);
+    }
+    let now = Instant::now();
+
+    if settings.input_enabled != joycons.input_enabled {
+        joycons.input_enabled = settings.input_enabled;
+        for (_, tracker) in joycons.trackers.iter_mut() {
+            if settings.input_enabled {
+                tracker.resume();
+            } else {
+                tracker.pause();
+            }
+        }
+    }
+
+    for (_, wrapper) in joycons.trackers.iter_mut() {
+        let _span = info_span!(
+            "joycon",
+            gamepad = wrapper.gamepad.id,
+            serial_number = %wrapper.info.serial_number,
+        )
+        .entered();
+
+        wrapper.pressed_this_frame.clear();
+        wrapper.released_this_frame.clear();
+        wrapper.sync_stick_stream(&settings);
+
+        if let Some(poller) = &mut wrapper.poller {
+            if !poller.get_mut().unwrap().poll() {
+                // Detection disconnects the joycon, as with a polling thread.
+                wrapper.poller = None;
+            }
+        }
+
+        if !wrapper.announced {
+            if wrapper.last_report.read().is_none() {
+                continue;
+            }
+            events.connected(wrapper.gamepad, wrapper.info.product_string.clone());
+            wrapper.announced = true;
+            if !wrapper.calibration_valid {
+                calibration_warnings.send(JoyconCalibrationWarning {
+                    gamepad: wrapper.gamepad,
+                });
+            }
+        }
+
+        let paused = wrapper.released || !settings.input_enabled;
+        if paused && !wrapper.sent_paused_state {
+            send_paused_state(&mut events, wrapper);
+            wrapper.sent_paused_state = true;
+        }
+
+        let Some((mut report, received_at)) = wrapper.last_report.read() else { continue };
+
+        // A paused joycon's reports are still processed, so that its snapshot,
+        // battery and idle state stay up to date, but its gamepad events are
+        // dropped, and its last sent axis values are kept.
+        events.set_muted(paused);
+
+        // The polling thread may not have received a new report since the last
+        // frame, in which case the sticks haven't changed.
+        let sequence = report_sequence(&report);
+        if sequence.is_none() || sequence != wrapper.last_sequence {
+            wrapper.last_sequence = sequence;
+            wrapper.report_received_at = Some(received_at);
+
+            if let Some(hook) = &hooks.report {
+                hook(wrapper.gamepad, &mut report);
+            }
+
+            process_report(&mut events, wrapper, &settings, &report, sequence, now);
+
+            if let Some((side, state)) = wrapper.update_calibration(now) {
+                calibration_states.send(JoyconCalibrationStateChanged {
+                    gamepad: wrapper.gamepad,
+                    side,
+                    state,
+                });
+            }
+        }
+
+        // Buttons are checked every frame regardless, so that turbo buttons
+        // keep toggling. They're taken from the snapshot, which has any changes
+        // made by the report hook. A paused joycon's buttons stay released.
+        if let (false, Some(buttons)) = (
+            paused,
+            wrapper.snapshot.as_ref().map(|snapshot| snapshot.buttons),
+        ) {
+            let button_map = joycons
+                .button_maps
+                .iter()
+                .find(|(which, _)| *which == wrapper.info.which)
+                .map(|(_, map)| map);
+            send_button_events(&mut events, wrapper, &settings, button_map, &buttons, now);
+        }
+        events.set_muted(false);
+
+        update_idle(
+            &mut idle_events,
+            &mut wake_events,
+            wrapper,
+            &settings,
+            paused,
+            now,
+        );
+
+        if let Some(timeout) = joycons.idle_disconnect {
+            if !wrapper.idle_disconnected && now.duration_since(wrapper.last_input) >= timeout {
+                wrapper.idle_disconnected = true;
+                wrapper.disconnect_idle(joycons.idle_disconnect_action);
+                idle_disconnects.send(JoyconIdleDisconnected {
+                    gamepad: wrapper.gamepad,
+                    action: joycons.idle_disconnect_action,
+                });
+            }
+        }
+    }
+
+    joycons.events_last_update = events.sent();
+}
+
+fn process_report(
+    events: &mut GamepadEvents,
+    tracker: &mut Tracker,
+    settings: &JoyconSettings,
+    report: &JoyconReport,
+    sequence: Option<u8>,
+    now: Instant,
+) {
+    if let (true, Some(imu)) = (tracker.auto_layout, &report.imu) {
+        let layout = held_layout(average_accel(imu), tracker.layout);
+        if layout != tracker.layout {
+            tracker.set_layout(layout);
+        }
+    }
+
+    let sticks = tracker.stick_values(report);
+    tracker.native_sticks = Some(sticks);
+    tracker.raw_sticks = report.raw.standard().map(|standard| {
+        [standard.left_stick, standard.right_stick].map(|stick| (stick.x(), stick.y()))
+    });
+
+    let mut output_sticks = process_sticks(events, tracker, settings, sticks, now);
+
+    for output in std::mem::take(&mut tracker.stale_sticks) {
+        send_stick_event(events, tracker, output, [0.0, 0.0]);
+    }
+
+    if let (Some((output, sensitivity)), Some(imu)) = (tracker.gyro_stick, &report.imu) {
+        let stick = gyro_stick(imu, sensitivity);
+        send_stick_event(events, tracker, output, stick);
+        *output.select_mut(&mut output_sticks) = stick;
+    }
+
+    if let Some(imu) = &report.imu {
+        tracker.steering.update(average_accel(imu));
+    }
+
+    let [left_stick, right_stick] = output_sticks.map(|[x, y]| Vec2::new(x as f32, y as f32));
+    tracker.snapshot = Some(JoyconSnapshot {
+        sequence,
+        left_stick,
+        right_stick,
+        buttons: report.buttons,
+        battery: report.battery,
+        imu: report.imu,
+    });
+}
+
+/// Sends the controller's physical sticks, from their native values. Each one
+/// is zeroed, rotated, filtered, then put in the axis convention, in that
+/// order. Returns the values sent for each output stick.
+fn process_sticks(
+    events: &mut GamepadEvents,
+    tracker: &mut Tracker,
+    settings: &JoyconSettings,
+    sticks: [[f64; 2]; 2],
+    now: Instant,
+) -> [[f64; 2]; 2] {
+    let mut output_sticks = [[0.0, 0.0], [0.0, 0.0]];
+    for &side in StickSide::physical_sticks(tracker.info.which) {
+        side.select_mut(&mut tracker.drift)
+            .update(side.select(sticks), now);
+        let stick = apply_zero(side.select(sticks), side.select(tracker.stick_zero));
+        let stick = tracker.rotation.apply(stick);
+        let stick = side
+            .select_mut(&mut tracker.stick_filters)
+            .apply(stick, settings, now);
+        let stick = settings.axis_convention.apply(stick);
+        let output = tracker.output_stick(side);
+        send_stick_event(events, tracker, output, stick);
+        *output.select_mut(&mut output_sticks) = stick;
+    }
+    output_sticks
+}
+
+fn send_button_events(
+    events: &mut GamepadEvents,
+    tracker: &mut Tracker,
+    settings: &JoyconSettings,
+    button_map: Option<&ButtonMap>,
+    buttons: &ButtonsStatus,
+    now: Instant,
+) {
+    let mapping: &[(JoyconButton, GamepadButtonType)] = match button_map {
+        Some(map) => &map.buttons,
+        None => button_mapping(tracker.info.which, tracker.layout),
+    };
+    let hat = match tracker.dpad_mode.unwrap_or(settings.dpad_mode) {
+        DpadMode::Buttons => None,
+        DpadMode::Hat => dpad_hat(tracker.info.which, tracker.layout, buttons),
+    };
+    // With a hat, the directions are only reported through it.
+    let mapping = mapping
+        .iter()
+        .filter(|(joycon_button, _)| hat.is_none() || !joycon_button.is_direction())
+        .map(|&mapped| assigned_stick_press(tracker.info.which, tracker.stick_assignment, mapped))
+        .collect::<Vec<_>>();
+
+    tracker
+        .suppressed_buttons
+        .retain(|button| button.is_pressed(buttons));
+    for chord in &tracker.chords {
+        if chord.suppress && chord.is_held(buttons) {
+            tracker
+                .suppressed_buttons
+                .extend(chord.buttons.iter().copied());
+        }
+    }
+
+    // Buttons that aren't mapped anymore, e.g. after changing layouts, would
+    // otherwise stay pressed.
+    let is_mapped = |button: GamepadButtonType| {
+        mapping.iter().any(|&(_, mapped)| mapped == button)
+            || tracker.chords.iter().any(|chord| chord.button == button)
+    };
+    let unmapped = tracker
+        .pressed_buttons
+        .keys()
+        .copied()
+        .filter(|&button| !is_mapped(button))
+        .collect::<Vec<_>>();
+    for button_type in unmapped {
+        set_button_pressed(events, tracker, button_type, false, now);
+    }
+
+    for &(joycon_button, button_type) in &mapping {
+        let mut pressed = joycon_button.is_pressed(buttons)
+            && !tracker.suppressed_buttons.contains(&joycon_button);
+        if let Some(turbo) = tracker.turbo.get_mut(&button_type) {
+            pressed = turbo.apply(pressed, now);
+        }
+
+        set_button_pressed(events, tracker, button_type, pressed, now);
+    }
+
+    let chords = tracker
+        .chords
+        .iter()
+        .map(|chord| (chord.button, chord.is_held(buttons)))
+        .collect::<Vec<_>>();
+    for (button_type, held) in chords {
+        set_button_pressed(events, tracker, button_type, held, now);
+    }
+
+    send_dpad_hat(events, tracker, hat.unwrap_or([0.0, 0.0]));
+}
+
+/// Sends events for the D-pad hat axes that changed. Axes that were never
+/// sent count as centered, so controllers not using a hat never send any.
+fn send_dpad_hat(events: &mut GamepadEvents, tracker: &mut Tracker, [x, y]: [f32; 2]) {
+    for (axis, value) in [(GamepadAxisType::DPadX, x), (GamepadAxisType::DPadY, y)] {
+        let old = tracker.axis_values.get(&axis).copied();
+        if old.unwrap_or(0.0) != value {
+            tracker.axis_values.insert(axis, value);
+            events.axis_changed(tracker.gamepad, axis, old, value);
+        }
+    }
+}
+
+/// Sends an event for the button if its state changed.
+fn set_button_pressed(
+    events: &mut GamepadEvents,
+    tracker: &mut Tracker,
+    button_type: GamepadButtonType,
+    pressed: bool,
+    now: Instant,
+) {
+    let changed = if pressed {
+        if tracker.pressed_buttons.contains_key(&button_type) {
+            false
+        } else {
+            tracker.pressed_buttons.insert(button_type, now);
+            true
+        }
+    } else {
+        tracker.pressed_buttons.remove(&button_type).is_some()
+    };
+
+    if changed {
+        let value = if pressed { 1.0 } else { 0.0 };
+        events.button_changed(tracker.gamepad, button_type, value);
+        if pressed {
+            tracker.pressed_this_frame.insert(button_type);
+        } else {
+            tracker.released_this_frame.insert(button_type);
+        }
+    }
+}
+
+/// Keeps track of when the joycon last had input, and sends idle and wake
+/// events.
+fn update_idle(
+    idle_events: &mut EventWriter<JoyconIdle>,
+    wake_events: &mut EventWriter<JoyconWake>,
+    tracker: &mut Tracker,
+    settings: &JoyconSettings,
+    paused: bool,
+    now: Instant,
+) {
+    tracker.active_sticks = tracker.snapshot.as_ref().map_or([false; 2], |snapshot| {
+        [snapshot.left_stick, snapshot.right_stick]
+            .map(|stick| stick.length() > settings.idle_stick_threshold)
+    });
+    let sticks_moved = tracker.active_sticks.contains(&true);
+
+    let hat_pressed = [GamepadAxisType::DPadX, GamepadAxisType::DPadY]
+        .iter()
+        .any(|axis| tracker.axis_values.get(axis).unwrap_or(&0.0) != &0.0);
+
+    // A paused joycon doesn't send its buttons, so they're read from the
+    // snapshot instead.
+    let buttons_pressed = if paused {
+        tracker.snapshot.as_ref().map_or(false, |snapshot| {
+            JoyconButton::ALL
+                .iter()
+                .any(|button| button.is_pressed(&snapshot.buttons))
+        })
+    } else {
+        !tracker.pressed_buttons.is_empty()
+    };
+
+    if sticks_moved || hat_pressed || buttons_pressed {
+        tracker.last_input = now;
+        tracker.had_input = true;
+        tracker.idle_disconnected = false;
+        if tracker.idle {
+            tracker.idle = false;
+            wake_events.send(JoyconWake {
+                gamepad: tracker.gamepad,
+            });
+        }
+        return;
+    }
+
+    let Some(idle_timeout) = settings.idle_timeout else { return };
+    if !tracker.idle && now.duration_since(tracker.last_input) >= idle_timeout {
+        tracker.idle = true;
+        idle_events.send(JoyconIdle {
+            gamepad: tracker.gamepad,
+            since: tracker.last_input,
+        });
+    }
+}
+
+/// Centers the sticks and releases the buttons of a joycon that stopped sending
+/// input.
+fn send_paused_state(events: &mut GamepadEvents, tracker: &mut Tracker) {
+    for &side in StickSide::physical_sticks(tracker.info.which) {
+        let output = tracker.output_stick(side);
+        send_stick_event(events, tracker, output, [0.0, 0.0]);
+    }
+
+    if let Some((output, _)) = tracker.gyro_stick {
+        send_stick_event(events, tracker, output, [0.0, 0.0]);
+    }
+
+    for button_type in std::mem::take(&mut tracker.pressed_buttons).into_keys() {
+        events.button_changed(tracker.gamepad, button_type, 0.0);
+        tracker.released_this_frame.insert(button_type);
+    }
+
+    send_dpad_hat(events, tracker, [0.0, 0.0]);
+    tracker.active_sticks = [false; 2];
+}
+
+fn report_sequence(report: &JoyconReport) -> Option<u8> {
+    report.raw.standard().map(|standard| standard.timer)
+}
+
+fn send_stick_event(
+    events: &mut GamepadEvents,
+    tracker: &mut Tracker,
+    output: StickSide,
+    [x, y]: [f64; 2],
+) {
+    // The cache holds the last values sent, so it's left alone while muted.
+    // Resuming sends the current values again.
+    if events.is_muted() {
+        return;
+    }
+
+    let (x_axis, y_axis) = output.axes();
+    for (axis, value) in [(x_axis, x as f32), (y_axis, y as f32)] {
+        let old = tracker.axis_values.insert(axis, value);
+        events.axis_changed(tracker.gamepad, axis, old, value);
+    }
+}
+
+fn joycon_polling_thread(mut poller: DevicePoller, shutdown: Arc<AtomicBool>) {
+    // Tag everything logged by this thread with the joycon it's polling.
+    let _span = info_span!("joycon_polling", serial_number = %poller.serial_number).entered();
+
+    while !shutdown.load(Ordering::SeqCst) && poller.poll() {}
+}
+
+/// Reads reports from a joycon into its tracker's pinboard, either on a
+/// polling thread or, in immediate mode, in `update_joycon_data`.
+struct DevicePoller {
+    device: JoyconDevice,
+    control: DeviceControl,
+    product_string: String,
+    serial_number: String,
+    last_report: Arc<Pinboard<(JoyconReport, Instant)>>,
+    heartbeat: Arc<Mutex<Instant>>,
+    poll_error: Arc<Mutex<Option<String>>>,
+    warned_bad_format: bool,
+    /// The bytes of the last stored report.
+    last_raw_report: Vec<u8>,
+    stick_stream: Arc<Mutex<Option<StickStream>>>,
+}
+
+impl DevicePoller {
+    /// Sends pending commands and waits for the next report. Returns false if
+    /// the device hit an error, after which it shouldn't be polled anymore.
+    fn poll(&mut self) -> bool {
+        self.control.update(&mut self.device);
+
+        let report = match self.device.tick() {
+            Ok(x) => x,
+            Err(e) => {
+                error!(
+                    "Error updating '{}' ({}): {}",
+                    self.product_string, self.serial_number, e
+                );
+                // Set before clearing the report, which is what the main
+                // thread checks for.
+                *self.poll_error.lock().unwrap() = Some(e.to_string());
+                self.last_report.clear();
+                return false;
+            }
+        };
+        *self.heartbeat.lock().unwrap() = Instant::now();
+
+        // Controllers connected over USB, or in an unexpected mode, may send
+        // reports without the standard input data. Don't turn those into
+        // garbage input.
+        if report.raw.standard().is_none() {
+            if !self.warned_bad_format {
+                self.warned_bad_format = true;
+                warn!(
+                    "'{}' ({}) sent a report in an unexpected format, ignoring it. \
+                     Controllers connected over USB aren't supported yet.",
+                    self.product_string, self.serial_number
+                );
+            }
+            return true;
+        }
+
+        // A report that's byte-identical to the last one, timer included, is
+        // one the device sent again, and storing it would only cause redundant
+        // processing.
+        let raw_report = report.raw.as_bytes();
+        if raw_report == self.last_raw_report.as_slice() {
+            return true;
+        }
+        self.last_raw_report.clear();
+        self.last_raw_report.extend_from_slice(raw_report);
+
+        let sequence = report_sequence(&report);
+        let received_at = Instant::now();
+        if let Some(stream) = &*self.stick_stream.lock().unwrap() {
+            stream.send(&report, sequence, received_at);
+        }
+
+        self.last_report.set((report, received_at));
+        true
+    }
+}
+
+/// A [`StickCallback`], with a copy of the tracker's stick processing that's
+/// kept up to date by `update_joycon_data`.
+struct StickStream {
+    callback: StickCallback,
+    gamepad: Gamepad,
+    which: WhichController,
+    calibration_valid: bool,
+    custom_calibration: [Option<CustomCalibration>; 2],
+    stick_zero: [[f64; 2]; 2],
+    rotation: StickRotation,
+    axis_convention: AxisConvention,
+}
+
+impl StickStream {
+    fn send(&self, report: &JoyconReport, sequence: Option<u8>, received_at: Instant) {
+        let sticks = stick_values(report, self.calibration_valid, &self.custom_calibration);
+        let mut processed = [Vec2::ZERO; 2];
+        for &side in StickSide::physical_sticks(self.which) {
+            let stick = apply_zero(side.select(sticks), side.select(self.stick_zero));
+            let stick = self.rotation.apply(stick);
+            let [x, y] = self.axis_convention.apply(stick);
+            *side.select_mut(&mut processed) = Vec2::new(x as f32, y as f32);
+        }
+        let [left, right] = processed;
+        (self.callback)(
+            self.gamepad,
+            StickSample {
+                left,
+                right,
+                sequence,
+                received_at,
+            },
+        );
+    }
+}
+
+/// Returns the `(x, y)` values of the left and right sticks, from the raw stick
+/// data for sticks with a custom calibration, or if the controller's
+/// calibration is garbage.
+fn stick_values(
+    report: &JoyconReport,
+    calibration_valid: bool,
+    custom_calibration: &[Option<CustomCalibration>; 2],
+) -> [[f64; 2]; 2] {
+    let calibrated = [
+        [report.left_stick.x, report.left_stick.y],
+        [report.right_stick.x, report.right_stick.y],
+    ];
+    let Some(standard) = report.raw.standard() else {
+        return if calibration_valid {
+            calibrated
+        } else {
+            [[0.0, 0.0], [0.0, 0.0]]
+        };
+    };
+
+    let raw = [standard.left_stick, standard.right_stick];
+    [0, 1].map(|i| match custom_calibration[i] {
+        Some(custom) if custom.is_valid() => custom.apply(raw[i]),
+        None if calibration_valid => calibrated[i],
+        None => uncalibrated_stick_value(raw[i]),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy_ecs::{event::Events, system::SystemState, world::World};
+    use bevy_input::gamepad::GamepadEventType;
+
+    use super::*;
+
+    /// A tracker that isn't backed by a device, as if its joycon was just
+    /// opened.
+    fn test_tracker(which: WhichController, serial_number: &str, gamepad: Gamepad) -> Tracker {
+        let color = ControllerColor::default();
+        let info = JoyconInfo {
+            product_string: "Test joycon".to_string(),
+            serial_number: serial_number.to_string(),
+            which,
+            color,
+            color_available: false,
+            use_spi_colors: UseSPIColors::No,
+            colors: JoyconColors::new(&color, UseSPIColors::No),
+            capabilities: JoyconCapabilities::new(which, [0, 0]),
+            calibration_source: CalibrationSource::Factory,
+            connected_at: Instant::now(),
+            device_path: None,
+        };
+        let (commands, _) = channel();
+        Tracker::from_info(
+            info,
+            Arc::new(Pinboard::new_empty()),
+            gamepad,
+            true,
+            false,
+            commands,
+        )
+    }
+
+    #[test]
+    fn imported_settings_apply_to_connected_joycons() {
+        let mut joycons = Joycons::new(&JoyconsPlugin::default());
+        let gamepad = gamepad();
+        let tracker = test_tracker(WhichController::LeftJoyCon, "AA:BB", gamepad);
+        joycons.trackers.insert(gamepad, tracker);
+
+        let calibration = CustomCalibration::new([2048.0; 2], [1348.0; 2], [2748.0; 2]);
+        let settings = PersistedControllerSettings {
+            layout: JoyconLayout::UprightSolo,
+            rotation: StickRotation::Half,
+            stick_assignment: StickSide::Right,
+            stick_zero: [[0.1, -0.1], [0.0, 0.0]],
+            custom_calibration: [Some(calibration), None],
+        };
+        joycons.import_settings(&JoyconPersistedSettings {
+            controllers: [("AA:BB".to_string(), settings)].into_iter().collect(),
+        });
+
+        let tracker = joycons.tracker(gamepad).unwrap();
+        assert_eq!(tracker.custom_calibration, [Some(calibration), None]);
+        assert_eq!(tracker.runtime_settings().persisted(), settings);
+    }
+
+    #[test]
+    fn imported_settings_of_disconnected_joycons_are_exported_again() {
+        let mut joycons = Joycons::new(&JoyconsPlugin::default());
+        let settings = PersistedControllerSettings {
+            layout: JoyconLayout::UprightSolo,
+            rotation: StickRotation::None,
+            stick_assignment: StickSide::Left,
+            stick_zero: [[0.0, 0.2], [0.0, 0.0]],
+            custom_calibration: [None, None],
+        };
+        let imported = JoyconPersistedSettings {
+            controllers: [("AA:BB".to_string(), settings)].into_iter().collect(),
+        };
+        joycons.import_settings(&imported);
+
+        assert_eq!(joycons.export_settings(), imported);
+    }
+
+    /// Runs native stick values through `process_sticks`, returning the axis
+    /// events that were sent.
+    fn processed_axis_events(
+        tracker: &mut Tracker,
+        sticks: [[f64; 2]; 2],
+    ) -> Vec<(GamepadAxisType, f32)> {
+        let mut world = World::new();
+        world.init_resource::<Events<RawGamepadEvent>>();
+        let mut state = SystemState::<EventWriter<RawGamepadEvent>>::new(&mut world);
+
+        {
+            let mut writer = state.get_mut(&mut world);
+            let mut events = GamepadEvents::new(&mut writer, false);
+            let settings = JoyconSettings::default();
+            process_sticks(&mut events, tracker, &settings, sticks, Instant::now());
+        }
+
+        let events = world.resource::<Events<RawGamepadEvent>>();
+        events
+            .get_reader()
+            .iter(events)
+            .map(|event| match &event.event_type {
+                GamepadEventType::AxisChanged(axis, value) => (*axis, *value),
+                other => panic!("Unexpected event {:?}", other),
+            })
+            .collect()
+    }
+
+    fn gamepad() -> Gamepad {
+        Gamepad {
+            id: STARTING_GAMEPAD_ID,
+        }
+    }
+
+    #[test]
+    fn right_joycon_stick_is_sent_rotated_on_the_left_stick() {
+        let mut tracker = test_tracker(WhichController::RightJoyCon, "AA:BB", gamepad());
+        assert_eq!(
+            processed_axis_events(&mut tracker, [[0.0, 0.0], [1.0, 0.0]]),
+            [
+                (GamepadAxisType::LeftStickX, 0.0),
+                (GamepadAxisType::LeftStickY, -1.0)
+            ]
+        );
+    }
+
+    #[test]
+    fn pro_controller_sticks_are_sent_unrotated() {
+        let mut tracker = test_tracker(WhichController::ProController, "AA:BB", gamepad());
+        assert_eq!(
+            processed_axis_events(&mut tracker, [[1.0, 0.5], [-0.5, 1.0]]),
+            [
+                (GamepadAxisType::LeftStickX, 1.0),
+                (GamepadAxisType::LeftStickY, 0.5),
+                (GamepadAxisType::RightStickX, -0.5),
+                (GamepadAxisType::RightStickY, 1.0)
+            ]
+        );
+    }
+
+    #[test]
+    fn sticks_are_zeroed_before_being_rotated() {
+        let mut tracker = test_tracker(WhichController::LeftJoyCon, "AA:BB", gamepad());
+        tracker.stick_zero = [[0.5, 0.0], [0.0, 0.0]];
+        // Rotating first would give (-0.5, 1.0).
+        assert_eq!(
+            processed_axis_events(&mut tracker, [[1.0, 0.0], [0.0, 0.0]]),
+            [
+                (GamepadAxisType::LeftStickX, 0.0),
+                (GamepadAxisType::LeftStickY, 0.5)
+            ]
+        );
+    }
+
+    #[test]
+    fn muted_sticks_dont_change_the_sent_axis_values() {
+        let mut tracker = test_tracker(WhichController::ProController, "AA:BB", gamepad());
+        processed_axis_events(&mut tracker, [[1.0, 0.5], [0.0, 0.0]]);
+
+        let mut world = World::new();
+        world.init_resource::<Events<RawGamepadEvent>>();
+        let mut state = SystemState::<EventWriter<RawGamepadEvent>>::new(&mut world);
+        {
+            let mut writer = state.get_mut(&mut world);
+            let mut events = GamepadEvents::new(&mut writer, false);
+            events.set_muted(true);
+            let settings = JoyconSettings::default();
+            process_sticks(
+                &mut events,
+                &mut tracker,
+                &settings,
+                [[-1.0, 0.0], [0.0, 0.0]],
+                Instant::now(),
+            );
+        }
+
+        assert!(world.resource::<Events<RawGamepadEvent>>().is_empty());
+        assert_eq!(
+            tracker.axis_values.get(&GamepadAxisType::LeftStickX),
+            Some(&1.0)
+        );
+        assert_eq!(
+            tracker.axis_values.get(&GamepadAxisType::LeftStickY),
+            Some(&0.5)
+        );
+    }
 
-        last_report.set(report);
+    #[test]
+    fn fallback_product_string_names_known_and_unknown_controllers() {
+        assert_eq!(fallback_product_string(0x2006), "Joy-Con (L)");
+        assert_eq!(fallback_product_string(0x2007), "Joy-Con (R)");
+        assert_eq!(fallback_product_string(0x2009), "Pro Controller");
+        assert_eq!(fallback_product_string(0x200e), "Joy-Con Charging Grip");
+        assert_eq!(
+            fallback_product_string(0x2017),
+            "Nintendo controller (2017)"
+        );
     }
 }
@@ -0,0 +1,181 @@
+use std::{
+    f32::consts::FRAC_PI_2,
+    time::{Duration, Instant},
+};
+
+use bevy_math::{DVec3, Quat, Vec3};
+use joycon::IMU;
+
+use crate::buttons::JoyconLayout;
+
+/// How two controllers are tilted relative to each other, estimated from the
+/// direction of gravity measured by each of them. Accelerometers can't tell
+/// rotation around the vertical axis or position, so neither is included.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RelativePose {
+    /// Angle between the two controllers' measured gravity directions, in
+    /// radians. 0 when both are tilted the same way.
+    pub angle: f32,
+    /// Rotation from the first controller's gravity direction to the second
+    /// one's, in the controllers' sensor coordinates.
+    pub rotation: Quat,
+}
+
+impl RelativePose {
+    pub(crate) fn new(from_gravity: Vec3, to_gravity: Vec3) -> Option<Self> {
+        let from = from_gravity.try_normalize()?;
+        let to = to_gravity.try_normalize()?;
+
+        Some(Self {
+            angle: from.angle_between(to),
+            rotation: Quat::from_rotation_arc(from, to),
+        })
+    }
+}
+
+/// How much more gravity has to point along one of a single joycon's axes than
+/// the other for `held_layout` to switch layouts. Between the two, the layout
+/// is kept, so that it doesn't keep switching when held at an angle.
+const LAYOUT_SWITCH_RATIO: f32 = 2.0;
+
+/// Gravity along the joycon's face, in G, below which it's considered to be
+/// lying flat, and its layout is kept.
+const MIN_LAYOUT_GRAVITY: f32 = 0.5;
+
+/// Returns which layout a single joycon is held in, based on the direction of
+/// gravity. Gravity points along its long side when it's held upright, and
+/// across it when it's held sideways.
+pub(crate) fn held_layout(gravity: Vec3, current: JoyconLayout) -> JoyconLayout {
+    let along = gravity.x.abs();
+    let across = gravity.y.abs();
+    if along.hypot(across) < MIN_LAYOUT_GRAVITY {
+        current
+    } else if along > across * LAYOUT_SWITCH_RATIO {
+        JoyconLayout::UprightSolo
+    } else if across > along * LAYOUT_SWITCH_RATIO {
+        JoyconLayout::Sideways
+    } else {
+        current
+    }
+}
+
+/// Time between the motion samples in a report.
+const IMU_SAMPLE_INTERVAL: Duration = Duration::from_millis(5);
+
+/// A single accelerometer and gyro reading, as reported by the controller and
+/// scaled by its calibration, without any filtering.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ImuSample {
+    /// Acceleration in G, including gravity.
+    pub accel: DVec3,
+    /// Rotation rate in degrees per second.
+    pub gyro: DVec3,
+    /// When the sample was taken. Estimated from when its report was
+    /// received, so it's offset by the transmission latency, but the time
+    /// between samples is accurate.
+    pub time: Instant,
+}
+
+/// The motion samples from a controller's last report, oldest first.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ImuSamples {
+    pub samples: [ImuSample; 3],
+    /// Time between consecutive samples.
+    pub interval: Duration,
+}
+
+impl ImuSamples {
+    pub(crate) fn new(imu: &[IMU; 3], received_at: Instant) -> Self {
+        let last = imu.len() - 1;
+        let samples = std::array::from_fn(|i| {
+            let sample = &imu[i];
+            let age = IMU_SAMPLE_INTERVAL * (last - i) as u32;
+            ImuSample {
+                accel: DVec3::new(sample.accel.x, sample.accel.y, sample.accel.z),
+                gyro: DVec3::new(sample.gyro.x, sample.gyro.y, sample.gyro.z),
+                time: received_at - age,
+            }
+        });
+
+        Self {
+            samples,
+            interval: IMU_SAMPLE_INTERVAL,
+        }
+    }
+}
+
+/// Turns how a controller is tilted into a steering wheel value, for a
+/// controller held like a wheel and turned around the axis through its face.
+/// The tilt is measured from gravity, so it doesn't drift, but it's disturbed
+/// by shaking the controller.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Steering {
+    /// Gravity direction while the wheel is centered. Taken from the first
+    /// motion data if it wasn't set.
+    center: Option<Vec3>,
+    /// Angle in radians at which the wheel is fully turned.
+    pub(crate) range: f32,
+}
+
+impl Default for Steering {
+    fn default() -> Self {
+        Self {
+            center: None,
+            range: FRAC_PI_2,
+        }
+    }
+}
+
+impl Steering {
+    pub(crate) fn update(&mut self, gravity: Vec3) {
+        self.center.get_or_insert(gravity);
+    }
+
+    pub(crate) fn recenter(&mut self, gravity: Vec3) {
+        self.center = Some(gravity);
+    }
+
+    /// The same settings, centered on the next motion data.
+    pub(crate) fn uncentered(self) -> Self {
+        Self {
+            center: None,
+            ..self
+        }
+    }
+
+    /// Returns how far the wheel is turned, from -1.0 (fully left) to 1.0
+    /// (fully right).
+    pub(crate) fn value(&self, gravity: Vec3) -> Option<f32> {
+        let center = self.center?.truncate().try_normalize()?;
+        let gravity = gravity.truncate().try_normalize()?;
+        Some((center.angle_between(gravity) / self.range).clamp(-1.0, 1.0))
+    }
+}
+
+/// Turns a report's rotation rates into a stick value, so that turning the
+/// controller faster pushes the stick further. Turning left and right around
+/// the controller's face moves the x axis, and tilting it around its long side
+/// moves the y axis, which matches a single joycon held sideways.
+pub(crate) fn gyro_stick(imu: &[IMU; 3], sensitivity: f64) -> [f64; 2] {
+    let (x, y) = imu.iter().fold((0.0, 0.0), |(x, y), sample| {
+        (x - sample.gyro.z, y + sample.gyro.x)
+    });
+    let samples = imu.len() as f64;
+    [
+        (x / samples * sensitivity).clamp(-1.0, 1.0),
+        (y / samples * sensitivity).clamp(-1.0, 1.0),
+    ]
+}
+
+/// Returns the average acceleration of a report's samples, in G. When the
+/// controller isn't moving much, this is the direction of gravity.
+pub(crate) fn average_accel(imu: &[IMU; 3]) -> Vec3 {
+    let sum = imu.iter().fold(Vec3::ZERO, |sum, sample| {
+        sum + Vec3::new(
+            sample.accel.x as f32,
+            sample.accel.y as f32,
+            sample.accel.z as f32,
+        )
+    });
+    sum / imu.len() as f32
+}
@@ -0,0 +1,35 @@
+use std::collections::BTreeMap;
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    buttons::JoyconLayout,
+    calibration::CustomCalibration,
+    stick::{StickRotation, StickSide},
+};
+
+/// Per-controller settings that are worth keeping between sessions, by serial
+/// number. Get them with `Joycons::export_settings`, and give them back with
+/// `Joycons::import_settings`. With the `serde` feature, this can be
+/// serialized to save it to disk.
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct JoyconPersistedSettings {
+    pub controllers: BTreeMap<String, PersistedControllerSettings>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct PersistedControllerSettings {
+    pub layout: JoyconLayout,
+    pub rotation: StickRotation,
+    pub stick_assignment: StickSide,
+    /// Offsets set by `Joycons::zero_stick`, for the left and right physical
+    /// sticks.
+    pub stick_zero: [[f64; 2]; 2],
+    /// Calibrations made with `Joycons::start_calibration`, for the left and
+    /// right physical sticks. Missing from settings saved by older versions.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub custom_calibration: [Option<CustomCalibration>; 2],
+}
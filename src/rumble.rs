@@ -0,0 +1,100 @@
+use std::{
+    ops::RangeInclusive,
+    time::{Duration, Instant},
+};
+
+/// Rumble frequencies that joycons play reliably, in Hz. Envelopes are played
+/// on both the low band, which goes from about 41 to 626 Hz, and the high band,
+/// which goes from about 82 to 1252 Hz, so this is where the two overlap.
+/// Others may be played at a different frequency or not at all, depending on
+/// the firmware.
+pub const RUMBLE_FREQUENCY_RANGE: RangeInclusive<f32> = 82.0..=626.0;
+
+/// Rumble amplitudes that are safe to play. Higher ones can damage the
+/// motors.
+pub const RUMBLE_AMPLITUDE_RANGE: RangeInclusive<f32> = 0.0..=1.0;
+
+/// What to do with rumble that's outside of [`RUMBLE_FREQUENCY_RANGE`] or
+/// [`RUMBLE_AMPLITUDE_RANGE`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum RumbleRangePolicy {
+    /// Play it at the closest values in range. NaN is played as the lowest
+    /// value.
+    #[default]
+    Clamp,
+    /// Don't play it, and log a warning.
+    Reject,
+}
+
+/// A rumble effect described by how its strength changes over time: it ramps
+/// up during `attack`, holds at `sustain_level` for `sustain`, then fades out
+/// during `release`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RumbleEnvelope {
+    pub attack: Duration,
+    /// Amplitude while sustaining, within [`RUMBLE_AMPLITUDE_RANGE`].
+    pub sustain_level: f32,
+    pub sustain: Duration,
+    pub release: Duration,
+    /// Frequency of the vibration, in Hz, within [`RUMBLE_FREQUENCY_RANGE`].
+    pub frequency: f32,
+}
+
+impl RumbleEnvelope {
+    /// Whether the frequency and amplitude are in the ranges that joycons
+    /// support.
+    pub fn is_in_range(&self) -> bool {
+        RUMBLE_FREQUENCY_RANGE.contains(&self.frequency)
+            && RUMBLE_AMPLITUDE_RANGE.contains(&self.sustain_level)
+    }
+
+    /// Returns the envelope with its frequency and amplitude clamped to the
+    /// ranges that joycons support.
+    pub fn clamped(self) -> Self {
+        Self {
+            frequency: clamp_to(self.frequency, &RUMBLE_FREQUENCY_RANGE),
+            sustain_level: clamp_to(self.sustain_level, &RUMBLE_AMPLITUDE_RANGE),
+            ..self
+        }
+    }
+
+    /// Returns the amplitude of the rumble `elapsed` after it started, or
+    /// `None` once it's over.
+    pub(crate) fn amplitude_at(&self, elapsed: Duration) -> Option<f32> {
+        let level = self.sustain_level;
+
+        if elapsed < self.attack {
+            return Some(level * elapsed.as_secs_f32() / self.attack.as_secs_f32());
+        }
+
+        let elapsed = elapsed - self.attack;
+        if elapsed < self.sustain {
+            return Some(level);
+        }
+
+        let elapsed = elapsed - self.sustain;
+        if elapsed < self.release {
+            return Some(level * (1.0 - elapsed.as_secs_f32() / self.release.as_secs_f32()));
+        }
+
+        None
+    }
+}
+
+/// A rumble envelope that's playing, returned by `Joycons::get_rumble`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RumbleState {
+    /// The envelope, after applying `JoyconsPlugin::rumble_range_policy`.
+    pub envelope: RumbleEnvelope,
+    pub started: Instant,
+    /// The envelope's amplitude at the time of the call.
+    pub amplitude: f32,
+}
+
+fn clamp_to(value: f32, range: &RangeInclusive<f32>) -> f32 {
+    if value.is_nan() {
+        *range.start()
+    } else {
+        value.clamp(*range.start(), *range.end())
+    }
+}
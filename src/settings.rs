@@ -0,0 +1,126 @@
+use std::time::Duration;
+
+use bevy_ecs::system::Resource;
+
+use crate::buttons::DpadMode;
+
+/// Global settings for how joycon input is turned into gamepad events. Can be
+/// changed at any time through `ResMut<JoyconSettings>`.
+///
+/// Joycon events go through Bevy's gamepad input pipeline like any other
+/// gamepad, so Bevy's `GamepadSettings` are still applied afterwards, including
+/// its default axis deadzone. This crate's own [`Self::deadzone`] is disabled
+/// by default to avoid stacking the two. If you enable it, consider setting
+/// the joycons' `AxisSettings` in `GamepadSettings` to not have a deadzone.
+#[derive(Resource, Clone, Debug)]
+pub struct JoyconSettings {
+    /// While false, no input events are sent for any joycon, as if they were
+    /// all released. Joycons stay connected, and their current state is sent
+    /// once input is enabled again.
+    pub input_enabled: bool,
+    /// Radial deadzone for sticks, from 0.0 (none, the default) to 1.0. Sticks
+    /// closer to the center than this report 0, and the rest of the range is
+    /// scaled so that values still start from 0 just outside the deadzone.
+    pub deadzone: f64,
+    /// Whether stick values are clamped to the unit circle, so that diagonals
+    /// aren't longer than straight directions. Defaults to
+    /// [`StickShape::Square`], which leaves values as they are. Applied after
+    /// [`Self::deadzone`], so the deadzone's rescaling can't push a value back
+    /// outside the circle, and before [`Self::stick_smoothing`].
+    pub stick_shape: StickShape,
+    /// How much stick values are smoothed, from 0.0 (no smoothing, the
+    /// default) up to just below 1.0. Each update, the previous value is
+    /// weighted by this factor and the new value by the rest. Smoothing reduces
+    /// noise at the cost of latency.
+    pub stick_smoothing: f64,
+    /// How long to suppress a stick's overshoot after it's released. Joycon
+    /// sticks spring back past the center when let go, briefly reading in the
+    /// opposite direction, which can trigger unwanted input. During this
+    /// window, values pointing away from where the stick was are reported as
+    /// centered. `None` (the default) disables the filter.
+    pub snapback_window: Option<Duration>,
+    /// How long a joycon must go without input before a `JoyconIdle` event is
+    /// sent for it. `None` (the default) disables idle detection.
+    pub idle_timeout: Option<Duration>,
+    /// How far from the center a stick has to be pushed to count as input for
+    /// idle detection, from 0.0 to 1.0. Defaults to 0.2, so that a bit of
+    /// drift doesn't keep a joycon awake.
+    pub idle_stick_threshold: f32,
+    /// How D-pads are reported, unless overridden for a controller with
+    /// `Joycons::set_dpad_mode`. Defaults to buttons.
+    pub dpad_mode: DpadMode,
+    /// How long a joycon's polling thread may go without hearing from the
+    /// device before it's considered stuck and reopened. Its gamepad
+    /// disconnects, and connects again once the device is reopened. Not
+    /// checked in immediate mode, where the device is only read once per frame.
+    /// Defaults to `None`, which disables the check.
+    pub report_timeout: Option<Duration>,
+    /// The range of stick axis values. Defaults to
+    /// [`AxisConvention::Normalized`].
+    pub axis_convention: AxisConvention,
+}
+
+impl Default for JoyconSettings {
+    fn default() -> Self {
+        Self {
+            input_enabled: true,
+            deadzone: 0.0,
+            stick_shape: StickShape::default(),
+            stick_smoothing: 0.0,
+            snapback_window: None,
+            idle_timeout: None,
+            idle_stick_threshold: 0.2,
+            dpad_mode: DpadMode::default(),
+            report_timeout: None,
+            axis_convention: AxisConvention::default(),
+        }
+    }
+}
+
+/// The range of values a stick may report, as an `(x, y)` vector.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum StickShape {
+    /// Values are passed through as-is, so diagonals can reach the corners of
+    /// the square range.
+    #[default]
+    Square,
+    /// Vectors longer than 1.0 are scaled down to the unit circle.
+    Circle,
+}
+
+impl StickShape {
+    pub(crate) fn apply(self, [x, y]: [f64; 2]) -> [f64; 2] {
+        match self {
+            StickShape::Square => [x, y],
+            StickShape::Circle => {
+                let magnitude = x.hypot(y);
+                if magnitude > 1.0 {
+                    [x / magnitude, y / magnitude]
+                } else {
+                    [x, y]
+                }
+            }
+        }
+    }
+}
+
+/// The range of the values sent for stick axes.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum AxisConvention {
+    /// Values are clamped to -1.0 to 1.0, the range Bevy expects, even if the
+    /// stick goes further than its calibration says or was zeroed off-center.
+    #[default]
+    Normalized,
+    /// Values are passed through unclamped, so they can go a bit past -1.0 and
+    /// 1.0, e.g. to measure how far a stick actually reaches.
+    Raw,
+}
+
+impl AxisConvention {
+    pub(crate) fn apply(self, [x, y]: [f64; 2]) -> [f64; 2] {
+        match self {
+            AxisConvention::Normalized => [x.clamp(-1.0, 1.0), y.clamp(-1.0, 1.0)],
+            AxisConvention::Raw => [x, y],
+        }
+    }
+}
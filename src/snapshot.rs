@@ -0,0 +1,66 @@
+use std::fmt::Write;
+
+use bevy_math::Vec2;
+use joycon::{
+    joycon_sys::input::{Battery, ButtonsStatus},
+    IMU,
+};
+
+/// The state of a joycon as of the last update, with the same processing that
+/// was applied to its gamepad events.
+#[derive(Clone, Debug)]
+pub struct JoyconSnapshot {
+    /// Timer byte of the report this snapshot was taken from.
+    pub sequence: Option<u8>,
+    /// The gamepad's left stick. Single joycons report their stick here,
+    /// unless it was assigned to the right stick.
+    pub left_stick: Vec2,
+    pub right_stick: Vec2,
+    pub buttons: ButtonsStatus,
+    pub battery: Battery,
+    /// Motion data, if the IMU was enabled with `Joycons::enable_imu` or
+    /// `JoyconsPlugin::default_imu`. Each report contains 3 samples.
+    pub imu: Option<[IMU; 3]>,
+}
+
+impl JoyconSnapshot {
+    /// Formats the snapshot as a human-readable block, one field per line.
+    pub(crate) fn dump(&self) -> String {
+        let mut dump = String::new();
+        // Writing to a String can't fail.
+        let _ = writeln!(dump, "sequence: {:?}", self.sequence);
+        let _ = writeln!(
+            dump,
+            "left stick: ({:.3}, {:.3})",
+            self.left_stick.x, self.left_stick.y
+        );
+        let _ = writeln!(
+            dump,
+            "right stick: ({:.3}, {:.3})",
+            self.right_stick.x, self.right_stick.y
+        );
+        let _ = writeln!(dump, "buttons: {:?}", self.buttons);
+        let _ = writeln!(dump, "battery: {:?}", self.battery);
+        match &self.imu {
+            Some(imu) => {
+                for (i, sample) in imu.iter().enumerate() {
+                    let _ = writeln!(
+                        dump,
+                        "imu[{}]: accel ({:.3}, {:.3}, {:.3}), gyro ({:.3}, {:.3}, {:.3})",
+                        i,
+                        sample.accel.x,
+                        sample.accel.y,
+                        sample.accel.z,
+                        sample.gyro.x,
+                        sample.gyro.y,
+                        sample.gyro.z,
+                    );
+                }
+            }
+            None => {
+                let _ = writeln!(dump, "imu: none");
+            }
+        }
+        dump
+    }
+}
@@ -0,0 +1,324 @@
+use std::time::{Duration, Instant};
+
+use bevy_input::gamepad::{GamepadAxisType, GamepadButtonType};
+use joycon::joycon_sys::input::WhichController;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::settings::JoyconSettings;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum StickSide {
+    #[default]
+    Left,
+    Right,
+}
+
+impl StickSide {
+    /// The physical sticks that a controller has.
+    pub(crate) fn physical_sticks(which: WhichController) -> &'static [StickSide] {
+        match which {
+            WhichController::LeftJoyCon => &[StickSide::Left],
+            WhichController::RightJoyCon => &[StickSide::Right],
+            WhichController::ProController => &[StickSide::Left, StickSide::Right],
+        }
+    }
+
+    /// Which gamepad stick a physical stick is reported as. The stick of a
+    /// single joycon is reported as `assignment`, whichever joycon it is.
+    pub(crate) fn output_stick(self, which: WhichController, assignment: StickSide) -> StickSide {
+        match which {
+            WhichController::LeftJoyCon | WhichController::RightJoyCon => assignment,
+            WhichController::ProController => self,
+        }
+    }
+
+    pub(crate) fn axes(self) -> (GamepadAxisType, GamepadAxisType) {
+        match self {
+            StickSide::Left => (GamepadAxisType::LeftStickX, GamepadAxisType::LeftStickY),
+            StickSide::Right => (GamepadAxisType::RightStickX, GamepadAxisType::RightStickY),
+        }
+    }
+
+    /// The button for pressing the gamepad stick in.
+    pub(crate) fn thumb_button(self) -> GamepadButtonType {
+        match self {
+            StickSide::Left => GamepadButtonType::LeftThumb,
+            StickSide::Right => GamepadButtonType::RightThumb,
+        }
+    }
+
+    pub(crate) fn select<T>(self, [left, right]: [T; 2]) -> T {
+        match self {
+            StickSide::Left => left,
+            StickSide::Right => right,
+        }
+    }
+
+    pub(crate) fn select_mut<T>(self, [left, right]: &mut [T; 2]) -> &mut T {
+        match self {
+            StickSide::Left => left,
+            StickSide::Right => right,
+        }
+    }
+}
+
+/// Rotation applied to a controller's stick values before they're sent as
+/// gamepad events.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum StickRotation {
+    #[default]
+    None,
+    Clockwise90,
+    CounterClockwise90,
+    Half,
+}
+
+impl StickRotation {
+    /// Single joycons are held sideways, so by default their sticks are
+    /// rotated to match.
+    pub fn default_for(which: WhichController) -> Self {
+        match which {
+            WhichController::LeftJoyCon => StickRotation::CounterClockwise90,
+            WhichController::RightJoyCon => StickRotation::Clockwise90,
+            WhichController::ProController => StickRotation::None,
+        }
+    }
+
+    pub(crate) fn apply(self, [x, y]: [f64; 2]) -> [f64; 2] {
+        match self {
+            StickRotation::None => [x, y],
+            StickRotation::Clockwise90 => [y, -x],
+            StickRotation::CounterClockwise90 => [-y, x],
+            StickRotation::Half => [-x, -y],
+        }
+    }
+}
+
+/// Moves the center of a stick to `zero`. The result may be out of the stick's
+/// range, which is up to [`AxisConvention`](crate::AxisConvention).
+pub(crate) fn apply_zero([x, y]: [f64; 2], [zero_x, zero_y]: [f64; 2]) -> [f64; 2] {
+    [x - zero_x, y - zero_y]
+}
+
+/// Applies a radial deadzone, scaling the rest of the range so that there's no
+/// jump at the edge of the deadzone.
+fn apply_deadzone([x, y]: [f64; 2], deadzone: f64) -> [f64; 2] {
+    if deadzone <= 0.0 {
+        return [x, y];
+    }
+
+    let magnitude = x.hypot(y);
+    if magnitude <= deadzone || deadzone >= 1.0 {
+        return [0.0, 0.0];
+    }
+
+    let scale = (magnitude - deadzone) / (1.0 - deadzone) / magnitude;
+    [x * scale, y * scale]
+}
+
+/// Sticks closer to the center than this aren't smoothed, so that releasing a
+/// stick returns it to the center right away instead of trailing off.
+const SMOOTHING_RESET_MAGNITUDE: f64 = 0.1;
+const MAX_SMOOTHING: f64 = 0.99;
+
+/// Exponential moving average of a stick's values.
+#[derive(Clone, Copy, Debug, Default)]
+struct StickSmoother {
+    previous: Option<[f64; 2]>,
+}
+
+impl StickSmoother {
+    fn apply(&mut self, stick: [f64; 2], factor: f64) -> [f64; 2] {
+        let factor = factor.clamp(0.0, MAX_SMOOTHING);
+
+        let smoothed = match self.previous {
+            Some(previous) if stick[0].hypot(stick[1]) >= SMOOTHING_RESET_MAGNITUDE => {
+                [0, 1].map(|i| previous[i] * factor + stick[i] * (1.0 - factor))
+            }
+            _ => stick,
+        };
+
+        self.previous = Some(smoothed);
+        smoothed
+    }
+}
+
+/// How much a stick's distance from the center has to drop in a single report
+/// to count as the stick being released.
+const SNAPBACK_RELEASE_DROP: f64 = 0.5;
+
+/// Suppresses the overshoot of a stick that was just released: joycon sticks
+/// spring back past the center, briefly reading in the opposite direction.
+#[derive(Clone, Copy, Debug, Default)]
+struct SnapbackFilter {
+    previous: Option<[f64; 2]>,
+    /// Where the stick was before being released, and when it was released.
+    released: Option<([f64; 2], Instant)>,
+}
+
+impl SnapbackFilter {
+    /// After the stick is released, values pointing away from where it was
+    /// are reported as centered until `window` is over.
+    fn apply(&mut self, stick: [f64; 2], window: Option<Duration>, now: Instant) -> [f64; 2] {
+        let previous = self.previous.replace(stick);
+        let Some(window) = window else {
+            self.released = None;
+            return stick;
+        };
+
+        if let Some(previous) = previous {
+            if previous[0].hypot(previous[1]) - stick[0].hypot(stick[1]) >= SNAPBACK_RELEASE_DROP {
+                self.released = Some((previous, now));
+            }
+        }
+
+        let Some((direction, released_at)) = self.released else { return stick };
+        if now.duration_since(released_at) > window {
+            self.released = None;
+            return stick;
+        }
+
+        if stick[0] * direction[0] + stick[1] * direction[1] < 0.0 {
+            [0.0, 0.0]
+        } else {
+            stick
+        }
+    }
+}
+
+/// Processing state of a stick, for the filters that depend on its previous
+/// values.
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct StickFilters {
+    snapback: SnapbackFilter,
+    smoother: StickSmoother,
+}
+
+impl StickFilters {
+    /// Applies the snapback filter, deadzone, shape and smoothing, in that
+    /// order, to a stick that was already zeroed and rotated.
+    pub(crate) fn apply(
+        &mut self,
+        stick: [f64; 2],
+        settings: &JoyconSettings,
+        now: Instant,
+    ) -> [f64; 2] {
+        let stick = self.snapback.apply(stick, settings.snapback_window, now);
+        let stick = apply_deadzone(stick, settings.deadzone);
+        let stick = settings.stick_shape.apply(stick);
+        self.smoother.apply(stick, settings.stick_smoothing)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Runs a sequence of reports, 15ms apart, through the filters.
+    fn filter_sequence(settings: &JoyconSettings, sticks: &[[f64; 2]]) -> Vec<[f64; 2]> {
+        let mut filters = StickFilters::default();
+        let start = Instant::now();
+        sticks
+            .iter()
+            .enumerate()
+            .map(|(i, &stick)| {
+                let now = start + Duration::from_millis(15 * i as u64);
+                filters.apply(stick, settings, now)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn flick_and_release_returns_to_center() {
+        let settings = JoyconSettings {
+            deadzone: 0.1,
+            stick_smoothing: 0.5,
+            snapback_window: Some(Duration::from_millis(100)),
+            ..Default::default()
+        };
+        // Pushed all the way, released with an overshoot, then resting with
+        // a bit of noise.
+        let sticks = [
+            [0.0, 0.0],
+            [0.6, 0.0],
+            [1.0, 0.0],
+            [-0.3, 0.0],
+            [0.02, -0.01],
+        ];
+        let output = filter_sequence(&settings, &sticks);
+
+        assert!(output[2][0] > 0.5);
+        assert_eq!(output[3], [0.0, 0.0]);
+        assert_eq!(output[4], [0.0, 0.0]);
+    }
+
+    #[test]
+    fn smoothed_stick_returns_exactly_to_center() {
+        let settings = JoyconSettings {
+            stick_smoothing: 0.9,
+            ..Default::default()
+        };
+        let output = filter_sequence(&settings, &[[1.0, 1.0], [1.0, 1.0], [0.0, 0.0]]);
+
+        assert_eq!(output[2], [0.0, 0.0]);
+    }
+
+    #[test]
+    fn stick_returning_to_a_previous_value_reports_it_again() {
+        let settings = JoyconSettings::default();
+        let output = filter_sequence(&settings, &[[0.5, 0.0], [1.0, 0.0], [0.5, 0.0]]);
+
+        assert_eq!(output, [[0.5, 0.0], [1.0, 0.0], [0.5, 0.0]]);
+    }
+
+    /// Where a single stick ends up on the gamepad, with the default rotation
+    /// and assignment for the controller.
+    fn output(which: WhichController, side: StickSide, native: [f64; 2]) -> (StickSide, [f64; 2]) {
+        (
+            side.output_stick(which, StickSide::Left),
+            StickRotation::default_for(which).apply(native),
+        )
+    }
+
+    #[test]
+    fn left_joycon_is_rotated_counter_clockwise() {
+        let which = WhichController::LeftJoyCon;
+        assert_eq!(
+            output(which, StickSide::Left, [1.0, 0.0]),
+            (StickSide::Left, [0.0, 1.0])
+        );
+        assert_eq!(
+            output(which, StickSide::Left, [0.0, 1.0]),
+            (StickSide::Left, [-1.0, 0.0])
+        );
+    }
+
+    #[test]
+    fn right_joycon_is_rotated_clockwise_onto_the_left_stick() {
+        let which = WhichController::RightJoyCon;
+        assert_eq!(
+            output(which, StickSide::Right, [1.0, 0.0]),
+            (StickSide::Left, [0.0, -1.0])
+        );
+        assert_eq!(
+            output(which, StickSide::Right, [0.0, 1.0]),
+            (StickSide::Left, [1.0, 0.0])
+        );
+    }
+
+    #[test]
+    fn pro_controller_is_not_rotated() {
+        let which = WhichController::ProController;
+        assert_eq!(
+            output(which, StickSide::Left, [1.0, 0.5]),
+            (StickSide::Left, [1.0, 0.5])
+        );
+        assert_eq!(
+            output(which, StickSide::Right, [-0.5, 1.0]),
+            (StickSide::Right, [-0.5, 1.0])
+        );
+    }
+}
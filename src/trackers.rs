@@ -0,0 +1,107 @@
+use bevy_input::gamepad::Gamepad;
+use bevy_utils::HashMap;
+use thunderdome::{Arena, Index};
+
+/// Connected trackers, looked up by arena index or by gamepad.
+///
+/// A removed tracker's index and gamepad never resolve again, even once its
+/// slot is reused: thunderdome bumps the slot's generation, and the gamepad is
+/// unmapped together with the tracker.
+pub(crate) struct Trackers<T> {
+    arena: Arena<(Gamepad, T)>,
+    by_gamepad: HashMap<Gamepad, Index>,
+}
+
+impl<T> Trackers<T> {
+    pub(crate) fn new() -> Self {
+        Self {
+            arena: Arena::new(),
+            by_gamepad: HashMap::default(),
+        }
+    }
+
+    pub(crate) fn insert(&mut self, gamepad: Gamepad, tracker: T) -> Index {
+        let index = self.arena.insert((gamepad, tracker));
+        // Gamepad ids aren't reused, but don't leave an unreachable tracker
+        // behind if one ever is.
+        if let Some(previous) = self.by_gamepad.insert(gamepad, index) {
+            self.arena.remove(previous);
+        }
+        index
+    }
+
+    pub(crate) fn get(&self, gamepad: Gamepad) -> Option<&T> {
+        let index = self.by_gamepad.get(&gamepad)?;
+        self.arena.get(*index).map(|(_, tracker)| tracker)
+    }
+
+    pub(crate) fn get_mut(&mut self, gamepad: Gamepad) -> Option<&mut T> {
+        let index = self.by_gamepad.get(&gamepad)?;
+        self.arena.get_mut(*index).map(|(_, tracker)| tracker)
+    }
+
+    /// Removes the tracker at `index`, returning `None` if it was already
+    /// removed.
+    pub(crate) fn remove(&mut self, index: Index) -> Option<T> {
+        let (gamepad, tracker) = self.arena.remove(index)?;
+        self.by_gamepad.remove(&gamepad);
+        Some(tracker)
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.arena.len()
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.arena.is_empty()
+    }
+
+    pub(crate) fn iter(&self) -> impl Iterator<Item = (Index, &T)> {
+        self.arena
+            .iter()
+            .map(|(index, (_, tracker))| (index, tracker))
+    }
+
+    pub(crate) fn iter_mut(&mut self) -> impl Iterator<Item = (Index, &mut T)> {
+        self.arena
+            .iter_mut()
+            .map(|(index, (_, tracker))| (index, tracker))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reused_slot_does_not_resolve_removed_tracker() {
+        let mut trackers = Trackers::new();
+        let first = Gamepad { id: 1 };
+        let second = Gamepad { id: 2 };
+
+        let first_index = trackers.insert(first, "first");
+        assert_eq!(trackers.remove(first_index), Some("first"));
+
+        let second_index = trackers.insert(second, "second");
+        assert_eq!(second_index.slot(), first_index.slot());
+
+        assert_eq!(trackers.get(first), None);
+        assert_eq!(trackers.remove(first_index), None);
+        assert_eq!(trackers.get(second), Some(&"second"));
+        assert_eq!(trackers.len(), 1);
+    }
+
+    #[test]
+    fn removing_unmaps_gamepad() {
+        let mut trackers = Trackers::new();
+        let gamepad = Gamepad { id: 1 };
+
+        let index = trackers.insert(gamepad, 0);
+        *trackers.get_mut(gamepad).unwrap() += 1;
+        assert_eq!(trackers.remove(index), Some(1));
+
+        assert_eq!(trackers.get(gamepad), None);
+        assert_eq!(trackers.get_mut(gamepad), None);
+        assert!(trackers.is_empty());
+    }
+}